@@ -1,15 +1,17 @@
 use teloxide::prelude::*;
-use teloxide::types::InputFile;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, InputFile, InputMedia, InputMediaPhoto};
 // Removed: use teloxide::utils::command::BotCommands;
 
 use scraper::{Html, Selector}; // Existing dependency
 use reqwest::Url;
-use serde::Deserialize;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::Mutex;
 use once_cell::sync::Lazy;
-use log::{info, error, debug};
+use log::{info, warn, error, debug};
 
 // Existing constants and structs
 const BASE_URL: &str = "https://paste.compucalitv.lol/?v="; // Existing
@@ -22,6 +24,599 @@ struct ScanPattern {
     template: String,
 }
 
+impl ScanPattern {
+    /// Number of URLs the pattern expands to.
+    fn value_count(&self) -> usize {
+        (self.start_char as u8).abs_diff(self.end_char as u8) as usize + 1
+    }
+}
+
+// --- Site Profiles ---
+// Profiles are loaded from a JSON file (SCRAPER_SITES_FILE, default "sites.json").
+// When the file is missing, a single built-in profile matching BASE_URL is used.
+const SITES_FILE_ENV: &str = "SCRAPER_SITES_FILE";
+const DEFAULT_SITES_FILE: &str = "sites.json";
+const DEFAULT_SITE_NAME: &str = "compucalitv";
+const DEFAULT_PROBE_RANGE_BYTES: u64 = 4096;
+const DEFAULT_JSON_TITLE_PATH: &str = "$.title";
+const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+const TELEGRAM_CAPTION_LIMIT: usize = 1024;
+const SCAN_PROGRESS_INTERVAL_SECONDS: u64 = 10;
+const HIT_MESSAGE_MAX_LINKS: usize = 10;
+const HIT_DESCRIPTION_MAX_CHARS: usize = 200;
+const HIT_MESSAGE_MAX_CAPTURES: usize = 10;
+const HIT_MESSAGE_MAX_JSON_LD: usize = 5;
+// Room left after the hit text for lines appended later (e.g. the /archive reference)
+const HIT_MESSAGE_RESERVED_CHARS: usize = 200;
+
+/// How `check_page` probes a URL before committing to a full download.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum ProbeStrategy {
+    /// Always download and parse the full body (original behaviour).
+    #[default]
+    Get,
+    /// Issue a HEAD first; only GET the body if status, length and redirect target look promising.
+    Head,
+    /// Fetch only the first bytes of the page; only GET the full body if that prefix looks promising.
+    RangeGet,
+}
+
+/// How the body of a scanned page is interpreted.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum ScanMode {
+    /// Parse HTML and use the `<title>` element.
+    #[default]
+    Html,
+    /// Parse JSON and extract the title and fields with JSONPath expressions.
+    Json,
+}
+
+/// A named value extracted from JSON responses, e.g. `{ "name": "autor", "path": "$.data.author" }`.
+#[derive(Deserialize, Debug, Clone)]
+struct JsonField {
+    name: String,
+    path: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct SiteProfile {
+    name: String,
+    /// Prefix the substituted pattern value is appended to.
+    base_url: String,
+    /// Titles that mark a page as a miss (e.g. the site's generic placeholder title).
+    #[serde(default)]
+    unwanted_titles: Vec<String>,
+    #[serde(default)]
+    probe: ProbeStrategy,
+    /// Pages whose Content-Length is below this are discarded without downloading the body.
+    #[serde(default)]
+    min_content_length: Option<u64>,
+    /// Number of bytes requested by the `range_get` strategy.
+    #[serde(default = "default_probe_range_bytes")]
+    probe_range_bytes: u64,
+    #[serde(default)]
+    redirects: RedirectPolicy,
+    #[serde(default)]
+    mode: ScanMode,
+    /// JSONPath for the value used as the hit title in `json` mode.
+    #[serde(default = "default_json_title")]
+    json_title: String,
+    /// Extra JSONPath fields shown with each hit in `json` mode.
+    #[serde(default)]
+    json_fields: Vec<JsonField>,
+    /// Regexes run over the page text; named capture groups are reported with each hit.
+    #[serde(default, deserialize_with = "deserialize_regexes")]
+    extract_regex: Vec<Regex>,
+    /// Custom request (method, headers, body). Without it a plain GET to `base_url` + value is sent.
+    #[serde(default)]
+    request: Option<RequestTemplate>,
+    /// Cookie jar, extra headers and optional login for sites that need an authenticated user.
+    #[serde(default)]
+    session: Option<SessionConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct SessionConfig {
+    /// Headers added to every request of this profile (including the login step).
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// Keep the cookie jar on disk so the session survives restarts.
+    #[serde(default = "default_true")]
+    persist_cookies: bool,
+    #[serde(default)]
+    login: Option<LoginStep>,
+    /// Body texts that mean the session is no longer valid (e.g. the login form's title).
+    #[serde(default)]
+    expired_markers: Vec<String>,
+}
+
+/// Form POST performed to obtain session cookies. `${VAR}` in form values is read from the environment.
+#[derive(Deserialize, Debug, Clone)]
+struct LoginStep {
+    url: String,
+    #[serde(default)]
+    form: HashMap<String, String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Request sent for each scanned value. Every `{}` in `url`, header values, form values and
+/// JSON string leaves is replaced by the value generated from the pattern.
+#[derive(Deserialize, Debug, Clone)]
+struct RequestTemplate {
+    #[serde(default = "default_request_method", deserialize_with = "deserialize_method")]
+    method: reqwest::Method,
+    /// Full URL template; defaults to `base_url` + value.
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// Sent as `application/x-www-form-urlencoded`.
+    #[serde(default)]
+    form: Option<HashMap<String, String>>,
+    /// Sent as `application/json`; ignored when `form` is set.
+    #[serde(default)]
+    json: Option<serde_json::Value>,
+}
+
+fn default_request_method() -> reqwest::Method {
+    reqwest::Method::GET
+}
+
+fn deserialize_method<'de, D>(deserializer: D) -> Result<reqwest::Method, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let method = String::deserialize(deserializer)?;
+    reqwest::Method::from_bytes(method.to_ascii_uppercase().as_bytes()).map_err(serde::de::Error::custom)
+}
+
+/// Replaces `{}` in every string leaf of a JSON body template.
+fn fill_json_template(template: &serde_json::Value, value: &str) -> serde_json::Value {
+    match template {
+        serde_json::Value::String(s) => serde_json::Value::String(s.replace("{}", value)),
+        serde_json::Value::Array(items) => serde_json::Value::Array(items.iter().map(|v| fill_json_template(v, value)).collect()),
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.iter().map(|(k, v)| (k.clone(), fill_json_template(v, value))).collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn deserialize_regexes<'de, D>(deserializer: D) -> Result<Vec<Regex>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| Regex::new(pattern).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Per-site rules for following redirects and classifying where they land.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+struct RedirectPolicy {
+    /// Follow redirects at all; when false any 3xx response is a miss.
+    follow: bool,
+    max_hops: usize,
+    /// Stop (and count as a miss) when a redirect leaves the original host.
+    same_host_only: bool,
+    /// Treat landing on the site's home page as a miss.
+    home_is_miss: bool,
+    /// URL prefixes that also count as a miss when a redirect lands on them (e.g. error or login pages).
+    miss_urls: Vec<String>,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        RedirectPolicy {
+            follow: true,
+            max_hops: 5,
+            same_host_only: false,
+            home_is_miss: true,
+            miss_urls: Vec::new(),
+        }
+    }
+}
+
+fn default_probe_range_bytes() -> u64 {
+    DEFAULT_PROBE_RANGE_BYTES
+}
+
+fn default_json_title() -> String {
+    DEFAULT_JSON_TITLE_PATH.to_string()
+}
+
+impl SiteProfile {
+    fn builtin() -> Self {
+        SiteProfile {
+            name: DEFAULT_SITE_NAME.to_string(),
+            base_url: BASE_URL.to_string(),
+            unwanted_titles: vec![TEXTO_NO_DESEADO.to_string()],
+            probe: ProbeStrategy::Get,
+            min_content_length: None,
+            probe_range_bytes: DEFAULT_PROBE_RANGE_BYTES,
+            redirects: RedirectPolicy::default(),
+            mode: ScanMode::Html,
+            json_title: default_json_title(),
+            json_fields: Vec::new(),
+            extract_regex: Vec::new(),
+            request: None,
+            session: None,
+        }
+    }
+
+    fn target_url(&self, value: &str) -> String {
+        match self.request.as_ref().and_then(|r| r.url.as_ref()) {
+            Some(template) => template.replace("{}", value),
+            None => format!("{}{}", self.base_url, value),
+        }
+    }
+
+    /// Host the scan requests go to, used as the pacing key.
+    fn host(&self) -> String {
+        Url::parse(&self.target_url("")).ok().and_then(|u| u.host_str().map(ToOwned::to_owned)).unwrap_or_default()
+    }
+
+    fn request_method(&self) -> reqwest::Method {
+        self.request.as_ref().map_or(reqwest::Method::GET, |r| r.method.clone())
+    }
+
+    /// Human-readable target used in messages: the URL, plus method and value when they aren't implied by it.
+    fn describe_target(&self, value: &str) -> String {
+        let url = self.target_url(value);
+        let method = self.request_method();
+        if method == reqwest::Method::GET && url.contains(value) {
+            url
+        } else {
+            format!("{} {} [{}]", method, url, value)
+        }
+    }
+
+    /// Builds the full request for `value` from the profile's request template.
+    fn build_request(&self, value: &str) -> Result<reqwest::Request, reqwest::Error> {
+        let url = self.target_url(value);
+        let Some(template) = &self.request else {
+            return HTTP_CLIENT.get(url).build();
+        };
+        let mut builder = HTTP_CLIENT.request(template.method.clone(), url);
+        for (name, header_value) in &template.headers {
+            builder = builder.header(name.as_str(), header_value.replace("{}", value));
+        }
+        if let Some(form) = &template.form {
+            let filled = form.iter().map(|(k, v)| (k.clone(), v.replace("{}", value))).collect::<HashMap<_, _>>();
+            builder = builder.form(&filled);
+        } else if let Some(json) = &template.json {
+            builder = builder.json(&fill_json_template(json, value));
+        }
+        builder.build()
+    }
+
+    fn is_unwanted_title(&self, title: &str) -> bool {
+        title.trim().is_empty() || self.unwanted_titles.iter().any(|t| t == title)
+    }
+
+    /// Root of the site (scheme + host), used to spot "redirected to home" misses.
+    fn home_url(&self) -> Option<Url> {
+        let mut url = Url::parse(&self.base_url).ok()?;
+        url.set_path("/");
+        url.set_query(None);
+        url.set_fragment(None);
+        Some(url)
+    }
+}
+
+static SITE_PROFILES: Lazy<Vec<SiteProfile>> = Lazy::new(load_site_profiles);
+
+// Redirects are followed by hand in `send_following_redirects` so the chain can be reported.
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("No se pudo construir el cliente HTTP")
+});
+
+fn load_site_profiles() -> Vec<SiteProfile> {
+    let path = std::env::var(SITES_FILE_ENV).unwrap_or_else(|_| DEFAULT_SITES_FILE.to_string());
+    match std::fs::read_to_string(&path) {
+        Ok(content) => match serde_json::from_str::<Vec<SiteProfile>>(&content) {
+            Ok(profiles) if !profiles.is_empty() => {
+                info!("Cargados {} perfiles de sitio desde {}", profiles.len(), path);
+                return profiles;
+            }
+            Ok(_) => error!("El archivo de perfiles {} está vacío, usando el perfil por defecto.", path),
+            Err(e) => error!("Error al parsear perfiles de sitio en {}: {}. Usando el perfil por defecto.", path, e),
+        },
+        Err(_) => debug!("No se encontró {}, usando el perfil de sitio por defecto.", path),
+    }
+    vec![SiteProfile::builtin()]
+}
+
+/// Looks up a site profile by name; `None` selects the first configured profile.
+fn find_site_profile(name: Option<&str>) -> Option<&'static SiteProfile> {
+    match name {
+        Some(name) => SITE_PROFILES.iter().find(|p| p.name.eq_ignore_ascii_case(name)),
+        None => SITE_PROFILES.first(),
+    }
+}
+
+// --- Local Persistence ---
+// Small JSON files under BOT_DATA_DIR (default "data") hold state that must survive restarts.
+const DATA_DIR_ENV: &str = "BOT_DATA_DIR";
+const DEFAULT_DATA_DIR: &str = "data";
+
+fn data_path(file_name: &str) -> std::path::PathBuf {
+    let dir = std::env::var(DATA_DIR_ENV).unwrap_or_else(|_| DEFAULT_DATA_DIR.to_string());
+    std::path::Path::new(&dir).join(file_name)
+}
+
+/// Loads a JSON state file, falling back to the default value when missing or unreadable.
+fn load_json_state<T: serde::de::DeserializeOwned + Default>(file_name: &str) -> T {
+    let path = data_path(file_name);
+    match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+            error!("Error al parsear {}: {}. Se usará un estado vacío.", path.display(), e);
+            T::default()
+        }),
+        Err(_) => T::default(),
+    }
+}
+
+/// Writes a JSON state file atomically (temp file + rename). Errors are only logged.
+fn save_json_state<T: serde::Serialize>(file_name: &str, value: &T) {
+    let path = data_path(file_name);
+    let result = (|| -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(value)?)?;
+        std::fs::rename(&tmp, &path)
+    })();
+    if let Err(e) = result {
+        error!("Error al guardar {}: {}", path.display(), e);
+    }
+}
+
+// --- Scan Sessions ---
+const COOKIES_FILE: &str = "cookies.json";
+
+/// Cookie name -> value for one site profile.
+type CookieJar = HashMap<String, String>;
+
+// Cookie jars per site profile name
+static SESSION_COOKIES: Lazy<Arc<Mutex<HashMap<String, CookieJar>>>> = Lazy::new(|| {
+    let mut jars: HashMap<String, CookieJar> = load_json_state(COOKIES_FILE);
+    jars.retain(|name, _| persists_cookies(name));
+    Arc::new(Mutex::new(jars))
+});
+
+/// Whether the profile named `name` opted into keeping its cookies on disk.
+fn persists_cookies(name: &str) -> bool {
+    SITE_PROFILES
+        .iter()
+        .any(|p| p.name == name && p.session.as_ref().is_some_and(|s| s.persist_cookies))
+}
+
+/// Writes only the jars of profiles with `persist_cookies`; the rest never reach the disk.
+fn save_persistent_cookies(jars: &HashMap<String, CookieJar>) {
+    let persistent = jars
+        .iter()
+        .filter(|(name, _)| persists_cookies(name))
+        .collect::<HashMap<_, _>>();
+    save_json_state(COOKIES_FILE, &persistent);
+}
+
+static ENV_PLACEHOLDER_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$\{([A-Za-z0-9_]+)\}").unwrap());
+
+/// Errors that abort a single page check.
+#[derive(Debug)]
+enum ScanError {
+    Http(reqwest::Error),
+    /// The site no longer accepts our session and logging in again didn't help.
+    SessionExpired(String),
+}
+
+impl std::fmt::Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanError::Http(e) => write!(f, "{}", e),
+            ScanError::SessionExpired(reason) => write!(f, "sesión expirada: {}", reason),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ScanError {
+    fn from(e: reqwest::Error) -> Self {
+        ScanError::Http(e)
+    }
+}
+
+fn expand_env_placeholders(template: &str) -> String {
+    ENV_PLACEHOLDER_RE
+        .replace_all(template, |caps: &regex::Captures| {
+            std::env::var(&caps[1]).unwrap_or_else(|_| {
+                error!("Variable de entorno {} no definida para el login", &caps[1]);
+                String::new()
+            })
+        })
+        .into_owned()
+}
+
+/// Adds the profile's session headers and stored cookies to an outgoing request.
+async fn apply_session(profile: &SiteProfile, request: &mut reqwest::Request) {
+    let Some(session) = &profile.session else { return };
+    for (name, value) in &session.headers {
+        match (reqwest::header::HeaderName::from_bytes(name.as_bytes()), reqwest::header::HeaderValue::from_str(value)) {
+            (Ok(name), Ok(value)) => {
+                request.headers_mut().insert(name, value);
+            }
+            _ => error!("Cabecera de sesión inválida en el perfil {}: {}", profile.name, name),
+        }
+    }
+    let jars = SESSION_COOKIES.lock().await;
+    if let Some(jar) = jars.get(&profile.name).filter(|jar| !jar.is_empty()) {
+        let cookie_header = jar.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("; ");
+        if let Ok(value) = reqwest::header::HeaderValue::from_str(&cookie_header) {
+            request.headers_mut().insert(reqwest::header::COOKIE, value);
+        }
+    }
+}
+
+/// Stores every `Set-Cookie` of a response in the profile's jar.
+async fn store_session_cookies(profile: &SiteProfile, res: &reqwest::Response) {
+    let Some(session) = &profile.session else { return };
+    let mut changed = false;
+    let mut jars = SESSION_COOKIES.lock().await;
+    let jar = jars.entry(profile.name.clone()).or_default();
+    for header in res.headers().get_all(reqwest::header::SET_COOKIE) {
+        let Ok(header) = header.to_str() else { continue };
+        let mut attributes = header.split(';').map(str::trim);
+        let Some((name, value)) = attributes.next().and_then(|pair| pair.split_once('=')) else { continue };
+        let expired = attributes.any(|a| a.eq_ignore_ascii_case("max-age=0"));
+        if value.is_empty() || expired {
+            changed |= jar.remove(name).is_some();
+        } else if jar.get(name).map(String::as_str) != Some(value) {
+            jar.insert(name.to_string(), value.to_string());
+            changed = true;
+        }
+    }
+    if changed && session.persist_cookies {
+        save_persistent_cookies(&jars);
+    }
+}
+
+async fn clear_session_cookies(profile: &SiteProfile) {
+    let mut jars = SESSION_COOKIES.lock().await;
+    if jars.remove(&profile.name).is_some() && profile.session.as_ref().is_some_and(|s| s.persist_cookies) {
+        save_persistent_cookies(&jars);
+    }
+}
+
+/// Performs the profile's login step (if any). Cookies are captured by `send_following_redirects`.
+async fn login_site(profile: &SiteProfile) -> Result<(), ScanError> {
+    let Some(login) = profile.session.as_ref().and_then(|s| s.login.as_ref()) else { return Ok(()) };
+    info!("Iniciando sesión en el perfil {} ({})", profile.name, login.url);
+    let form = login.form.iter().map(|(k, v)| (k.clone(), expand_env_placeholders(v))).collect::<HashMap<_, _>>();
+    let request = HTTP_CLIENT.post(&login.url).form(&form).build()?;
+    let followed = send_following_redirects(request, &profile.redirects, Some(profile)).await?;
+    let status = followed.response.status();
+    if !status.is_success() && !status.is_redirection() {
+        return Err(ScanError::SessionExpired(format!("el login respondió HTTP {}", status)));
+    }
+    Ok(())
+}
+
+/// Logs in before the first request of a profile when its jar holds no cookies yet.
+async fn ensure_session(profile: &SiteProfile) -> Result<(), ScanError> {
+    if profile.session.as_ref().is_none_or(|s| s.login.is_none()) {
+        return Ok(());
+    }
+    let has_cookies = SESSION_COOKIES.lock().await.get(&profile.name).is_some_and(|jar| !jar.is_empty());
+    if has_cookies { Ok(()) } else { login_site(profile).await }
+}
+
+/// Detects a lost session: landing on the login page or a body containing an expiry marker.
+fn session_expired_reason(profile: &SiteProfile, final_url: &Url, body: Option<&str>) -> Option<String> {
+    let session = profile.session.as_ref()?;
+    if let Some(login) = &session.login {
+        if final_url.as_str().starts_with(&login.url) {
+            return Some(format!("redirigido al login ({})", final_url));
+        }
+    }
+    let body = body?;
+    session
+        .expired_markers
+        .iter()
+        .find(|marker| body.contains(marker.as_str()))
+        .map(|marker| format!("la página contiene '{}'", marker))
+}
+
+// --- Adaptive Host Pacing ---
+// Requests to each host are spaced by an AIMD-controlled rate: it grows slowly while responses are
+// healthy and is cut in half on 429/5xx/timeouts. All scans hitting the same host share its pacer.
+const PACER_INITIAL_RATE: f64 = 0.5; // requests per second (the old fixed 2s delay)
+const PACER_MIN_RATE: f64 = 0.05;
+const PACER_MAX_RATE: f64 = 4.0;
+const PACER_ADDITIVE_STEP: f64 = 0.05;
+const PACER_DECREASE_FACTOR: f64 = 0.5;
+const PACER_MAX_RETRY_AFTER_SECONDS: u64 = 300;
+
+struct HostPacer {
+    rate: f64,
+    next_slot: tokio::time::Instant,
+}
+
+static HOST_PACERS: Lazy<Arc<Mutex<HashMap<String, HostPacer>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// Waits for the next request slot of `host` and reserves it.
+async fn pace_host(host: &str) {
+    let wait_until = {
+        let mut pacers = HOST_PACERS.lock().await;
+        let now = tokio::time::Instant::now();
+        let pacer = pacers.entry(host.to_string()).or_insert(HostPacer { rate: PACER_INITIAL_RATE, next_slot: now });
+        let slot = pacer.next_slot.max(now);
+        pacer.next_slot = slot + Duration::from_secs_f64(1.0 / pacer.rate);
+        slot
+    };
+    tokio::time::sleep_until(wait_until).await;
+}
+
+/// Feeds a response (or transport failure) back into the host's rate.
+async fn record_host_outcome(host: &str, throttled: bool, retry_after: Option<Duration>) {
+    let mut pacers = HOST_PACERS.lock().await;
+    let now = tokio::time::Instant::now();
+    let pacer = pacers.entry(host.to_string()).or_insert(HostPacer { rate: PACER_INITIAL_RATE, next_slot: now });
+    if throttled {
+        let previous = pacer.rate;
+        pacer.rate = (pacer.rate * PACER_DECREASE_FACTOR).max(PACER_MIN_RATE);
+        if let Some(retry_after) = retry_after {
+            pacer.next_slot = pacer.next_slot.max(now + retry_after);
+        }
+        info!("Host {} limitando peticiones; ritmo {:.2} -> {:.2} req/s", host, previous, pacer.rate);
+    } else {
+        pacer.rate = (pacer.rate + PACER_ADDITIVE_STEP).min(PACER_MAX_RATE);
+    }
+}
+
+async fn host_rate(host: &str) -> f64 {
+    HOST_PACERS.lock().await.get(host).map_or(PACER_INITIAL_RATE, |p| p.rate)
+}
+
+fn retry_after(res: &reqwest::Response) -> Option<Duration> {
+    res.headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(|secs| Duration::from_secs(secs.min(PACER_MAX_RETRY_AFTER_SECONDS)))
+}
+
+/// Executes one request through the host's pacer and reports the outcome back to it.
+async fn execute_paced(request: reqwest::Request) -> Result<reqwest::Response, reqwest::Error> {
+    let host = request.url().host_str().unwrap_or_default().to_string();
+    pace_host(&host).await;
+    match HTTP_CLIENT.execute(request).await {
+        Ok(res) => {
+            let status = res.status();
+            let throttled = status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+            record_host_outcome(&host, throttled, retry_after(&res)).await;
+            Ok(res)
+        }
+        Err(e) => {
+            if e.is_timeout() || e.is_connect() {
+                record_host_outcome(&host, true, None).await;
+            }
+            Err(e)
+        }
+    }
+}
+
 // --- YTS Integration Structs ---
 #[derive(Deserialize, Debug, Clone)]
 struct YtsApiResponse {
@@ -32,17 +627,59 @@ struct YtsApiResponse {
 
 #[derive(Deserialize, Debug, Clone)]
 struct YtsData {
+    #[serde(default)]
+    movie_count: u32,
     movies: Option<Vec<YtsMovie>>,
+    // Only present in movie_details responses
+    movie: Option<YtsMovie>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 struct YtsMovie {
     id: u32,
+    #[serde(default)]
+    title: String,
     title_long: String,
     year: u32,
     large_cover_image: String,
     torrents: Vec<YtsTorrent>,
-    // Add other fields if needed, like `date_uploaded_unix` for more precise ordering
+    // YTS omits some of these for older uploads, so all of them default
+    #[serde(default)]
+    rating: f32,
+    #[serde(default)]
+    genres: Vec<String>,
+    #[serde(default)]
+    language: String,
+    #[serde(default)]
+    runtime: u32,
+    #[serde(default)]
+    summary: String,
+    #[serde(default)]
+    imdb_code: String,
+    #[serde(default)]
+    mpa_rating: String,
+    #[serde(default)]
+    yt_trailer_code: String,
+    #[serde(default)]
+    date_uploaded_unix: i64,
+    // Only filled in by movie_details (with_images=true, with_cast=true)
+    #[serde(default)]
+    description_full: String,
+    #[serde(default)]
+    cast: Vec<YtsCastMember>,
+    #[serde(default)]
+    large_screenshot_image1: String,
+    #[serde(default)]
+    large_screenshot_image2: String,
+    #[serde(default)]
+    large_screenshot_image3: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct YtsCastMember {
+    name: String,
+    #[serde(default)]
+    character_name: String,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -50,124 +687,847 @@ struct YtsTorrent {
     url: String,
     hash: String,
     quality: String,
-    // Removed: torrent_type: String, // "type" is a reserved keyword in Rust
-    // Add other fields if needed
+    #[serde(rename = "type", default)]
+    torrent_type: String, // "web" or "bluray"; `type` is a reserved keyword in Rust
+    #[serde(default)]
+    video_codec: String,
+    #[serde(default)]
+    size: String,
+    #[serde(default)]
+    size_bytes: u64,
+    #[serde(default)]
+    seeds: u32,
+    #[serde(default)]
+    peers: u32,
 }
 
 // --- Global State for YTS Monitoring ---
-// Stores the ID of the latest movie processed globally by the bot
-static LAST_PROCESSED_YTS_MOVIE_ID: Lazy<Arc<Mutex<Option<u32>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
-// Stores chat IDs that are subscribed to YTS notifications
-static SUBSCRIBED_CHAT_IDS: Lazy<Arc<Mutex<Vec<ChatId>>>> = Lazy::new(|| Arc::new(Mutex::new(Vec::new())));
-// Stores the handle of the single YTS monitoring task
-static YTS_MONITOR_TASK_HANDLE: Lazy<Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
+// Subscriptions and the cursor are persisted in data/yts_state.json and restored on boot.
+const YTS_STATE_FILE: &str = "yts_state.json";
 
-const YTS_API_URL: &str = "https://yts.mx/api/v2/list_movies.json";
-const YTS_CHECK_INTERVAL_SECONDS: u64 = 180; // Check every 2.5 minutes
-const YTS_MOVIES_FETCH_LIMIT: u8 = 10; // Fetch up to 10 movies to check for new ones
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct YtsPersistedState {
+    subscribed_chat_ids: Vec<i64>,
+    last_processed_movie_id: Option<u32>,
+    #[serde(default)]
+    chat_filters: HashMap<i64, YtsFilters>,
+    #[serde(default)]
+    chat_policies: HashMap<i64, TorrentPolicy>,
+    /// Torrent hashes seen for each recent movie, to detect qualities added after the announcement.
+    #[serde(default)]
+    known_torrents: KnownTorrents,
+}
 
-// --- Helper Functions for YTS ---
+/// Torrent hashes (uppercase) seen for each movie id.
+type KnownTorrents = HashMap<u32, Vec<String>>;
 
-/// Fetches the latest movies from the YTS API.
-async fn fetch_latest_yts_movies(limit: u8) -> Result<Vec<YtsMovie>, reqwest::Error> {
-    // API sorts by date_added desc by default
-    let url = format!("{}?sort_by=date_added&order_by=desc&limit={}", YTS_API_URL, limit);
-    debug!("Fetching YTS movies from URL: {}", url);
-    let response = reqwest::get(&url).await?.json::<YtsApiResponse>().await?;
+/// Per-chat conditions a new movie must meet before it is announced. Empty fields match everything.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct YtsFilters {
+    #[serde(default)]
+    qualities: Vec<String>,
+    #[serde(default)]
+    min_rating: Option<f32>,
+    #[serde(default)]
+    genres: Vec<String>,
+    #[serde(default)]
+    min_year: Option<u32>,
+    #[serde(default)]
+    max_year: Option<u32>,
+    #[serde(default)]
+    languages: Vec<String>,
+    #[serde(default)]
+    min_runtime: Option<u32>,
+    #[serde(default)]
+    max_runtime: Option<u32>,
+}
 
-    if response.status == "ok" {
-        if let Some(data) = response.data {
-            if let Some(movies) = data.movies {
-                return Ok(movies);
+impl YtsFilters {
+    fn is_empty(&self) -> bool {
+        self.qualities.is_empty()
+            && self.min_rating.is_none()
+            && self.genres.is_empty()
+            && self.min_year.is_none()
+            && self.max_year.is_none()
+            && self.languages.is_empty()
+            && self.min_runtime.is_none()
+            && self.max_runtime.is_none()
+    }
+
+    /// Returns the movie as it should be announced to this chat: torrents are narrowed to the
+    /// wanted qualities, and `None` means the movie does not pass the filters.
+    fn apply(&self, movie: &YtsMovie) -> Option<YtsMovie> {
+        let in_range = |value: u32, min: Option<u32>, max: Option<u32>| {
+            min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+        };
+        if self.min_rating.is_some_and(|min| movie.rating < min)
+            || !in_range(movie.year, self.min_year, self.max_year)
+            // Unknown runtimes (0) are not discarded by a runtime range
+            || (movie.runtime > 0 && !in_range(movie.runtime, self.min_runtime, self.max_runtime))
+        {
+            return None;
+        }
+        if !self.genres.is_empty()
+            && !movie.genres.iter().any(|g| self.genres.iter().any(|wanted| wanted.eq_ignore_ascii_case(g)))
+        {
+            return None;
+        }
+        if !self.languages.is_empty() && !self.languages.iter().any(|l| l.eq_ignore_ascii_case(&movie.language)) {
+            return None;
+        }
+        let mut movie = movie.clone();
+        if !self.qualities.is_empty() {
+            movie.torrents.retain(|t| self.qualities.iter().any(|q| q.eq_ignore_ascii_case(&t.quality)));
+            if movie.torrents.is_empty() {
+                return None;
             }
         }
+        Some(movie)
     }
-    // If status is not "ok" or data/movies are missing, return empty or an error
-    error!("Failed to fetch movies or parse response: Status: {}, Message: {}", response.status, response.status_message);
-    Ok(Vec::new())
-}
-
-/// Constructs a magnet link for a given torrent hash and movie name.
-fn construct_magnet_link(hash: &str, movie_name: &str) -> String {
-    let encoded_movie_name = urlencoding::encode(movie_name);
-    // Trackers recommended by YTS API documentation
-    let trackers = [
-        "udp://open.demonii.com:1337/announce",
-        "udp://tracker.openbittorrent.com:80",
-        "udp://tracker.coppersurfer.tk:6969",
-        "udp://glotorrents.pw:6969/announce",
-        "udp://tracker.opentrackr.org:1337/announce",
-        "udp://torrent.gresille.org:80/announce",
-        "udp://p4p.arenabg.com:1337",
-        "udp://tracker.leechers-paradise.org:6969",
-    ];
-    let tracker_params = trackers.iter().map(|t| format!("&tr={}", urlencoding::encode(t))).collect::<String>();
-    format!("`magnet:?xt=urn:btih:{}&dn={}{}`", hash, encoded_movie_name, tracker_params)
-}
 
-/// Sends a movie notification (photo with caption) to a specified chat.
-async fn send_movie_notification(bot: &Bot, chat_id: ChatId, movie: &YtsMovie) -> ResponseResult<()> {
-    if movie.torrents.is_empty() {
-        info!("Movie '{}' (ID: {}) has no torrents, skipping notification.", movie.title_long, movie.id);
-        return Ok(());
+    /// Applies `key=value` arguments on top of the current filters. An empty value clears that key.
+    fn update(&mut self, args: &[String]) -> Result<(), String> {
+        fn list(value: &str) -> Vec<String> {
+            value.split(',').map(str::trim).filter(|v| !v.is_empty()).map(ToOwned::to_owned).collect()
+        }
+        fn range(value: &str) -> Result<(Option<u32>, Option<u32>), String> {
+            if value.is_empty() {
+                return Ok((None, None));
+            }
+            let bound = |v: &str| -> Result<Option<u32>, String> {
+                if v.is_empty() {
+                    Ok(None)
+                } else {
+                    v.parse().map(Some).map_err(|_| format!("Valor no válido: '{}'", v))
+                }
+            };
+            match value.split_once('-') {
+                Some((min, max)) => Ok((bound(min)?, bound(max)?)),
+                None => bound(value).map(|v| (v, v)),
+            }
+        }
+        for arg in args {
+            let (key, value) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("Argumento no reconocido: '{}'", arg))?;
+            match key.to_lowercase().as_str() {
+                "calidad" => self.qualities = list(value),
+                "rating" => {
+                    self.min_rating = if value.is_empty() {
+                        None
+                    } else {
+                        Some(value.parse().map_err(|_| format!("Rating no válido: '{}'", value))?)
+                    }
+                }
+                "genero" | "género" => self.genres = list(value),
+                "año" | "anio" => (self.min_year, self.max_year) = range(value)?,
+                "idioma" => self.languages = list(value),
+                "duracion" | "duración" => (self.min_runtime, self.max_runtime) = range(value)?,
+                other => return Err(format!("Filtro desconocido: '{}'", other)),
+            }
+        }
+        Ok(())
     }
 
-    // Select a torrent (e.g., the first one, or filter by quality)
-    let torrent = &movie.torrents[0]; // Taking the first available torrent
+    fn describe(&self) -> String {
+        if self.is_empty() {
+            return "sin filtros (se anuncian todas las películas)".to_string();
+        }
+        let range = |min: Option<u32>, max: Option<u32>| {
+            format!(
+                "{}-{}",
+                min.map(|v| v.to_string()).unwrap_or_default(),
+                max.map(|v| v.to_string()).unwrap_or_default()
+            )
+        };
+        let mut parts = Vec::new();
+        if !self.qualities.is_empty() {
+            parts.push(format!("calidad={}", self.qualities.join(",")));
+        }
+        if let Some(rating) = self.min_rating {
+            parts.push(format!("rating>={}", rating));
+        }
+        if !self.genres.is_empty() {
+            parts.push(format!("género={}", self.genres.join(",")));
+        }
+        if self.min_year.is_some() || self.max_year.is_some() {
+            parts.push(format!("año={}", range(self.min_year, self.max_year)));
+        }
+        if !self.languages.is_empty() {
+            parts.push(format!("idioma={}", self.languages.join(",")));
+        }
+        if self.min_runtime.is_some() || self.max_runtime.is_some() {
+            parts.push(format!("duración={} min", range(self.min_runtime, self.max_runtime)));
+        }
+        parts.join(" ")
+    }
+}
 
-    let magnet_link = construct_magnet_link(&torrent.hash, &movie.title_long);
-    let caption = format!(
-        "{}\nAño: {}\nCalidad: {}\n\nMagnet: {}\n\nTorrent URL: {}",
-        movie.title_long,
-        movie.year,
-        torrent.quality, // Added quality info
-        magnet_link,
-        torrent.url
-    );
+/// Per-chat rules to announce a single torrent instead of every version of a movie.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct TorrentPolicy {
+    /// Qualities in order of preference; unlisted qualities rank last.
+    #[serde(default)]
+    qualities: Vec<String>,
+    /// Preferred release type ("bluray" or "web").
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    prefer_x265: bool,
+    /// Break ties by the number of seeds instead of the API order.
+    #[serde(default)]
+    most_seeds: bool,
+    /// Torrents larger than this are never chosen.
+    #[serde(default)]
+    max_size_bytes: Option<u64>,
+}
 
-    if caption.len() > 1024 { // Telegram caption limit
-        error!("Caption for movie {} is too long ({} chars). Sending truncated or alternative.", movie.title_long, caption.len());
-        // Potentially send a shorter caption or multiple messages. For now, it might fail or be truncated by Telegram.
+impl TorrentPolicy {
+    /// Picks the torrent to announce, or `None` if every torrent exceeds the size limit.
+    fn select<'a>(&self, torrents: &'a [YtsTorrent]) -> Option<&'a YtsTorrent> {
+        torrents
+            .iter()
+            // Unknown sizes (0) are not discarded by the size limit
+            .filter(|t| self.max_size_bytes.is_none_or(|max| t.size_bytes == 0 || t.size_bytes <= max))
+            .min_by_key(|t| {
+                let quality_rank = self
+                    .qualities
+                    .iter()
+                    .position(|q| q.eq_ignore_ascii_case(&t.quality))
+                    .unwrap_or(self.qualities.len());
+                let wrong_source = self.source.as_ref().is_some_and(|s| !s.eq_ignore_ascii_case(&t.torrent_type));
+                let not_x265 = self.prefer_x265 && !t.video_codec.eq_ignore_ascii_case("x265");
+                let seeds = if self.most_seeds { t.seeds } else { 0 };
+                (quality_rank, wrong_source, not_x265, std::cmp::Reverse(seeds))
+            })
     }
-    
-    info!("Preparing to send notification for '{}' to chat {}", movie.title_long, chat_id);
 
-    match Url::parse(&movie.large_cover_image) {
-        Ok(img_url) => {
-            match bot.send_photo(chat_id, InputFile::url(img_url)).caption(caption).await {
-                Ok(_) => info!("Notification sent successfully for '{}' to chat {}", movie.title_long, chat_id),
-                Err(e) => {
-                    error!("Failed to send photo notification for '{}' to chat {}: {:?}. Trying text message.", movie.title_long, chat_id, e);
-                    // Fallback to text message if photo send fails (e.g. image too big, bot blocked by user etc.)
-                    let fallback_caption = format!(
-                        "Nueva Película: {}\nAño: {}\nCalidad: {}\nCover: {}\nMagnet: {}\nTorrent URL: {}",
-                        movie.title_long, movie.year, torrent.quality, movie.large_cover_image, magnet_link, torrent.url
-                    );
-                    bot.send_message(chat_id, fallback_caption).await?;
-                }
+    /// Applies `key=value` arguments on top of the current policy. An empty value clears that key.
+    fn update(&mut self, args: &[String]) -> Result<(), String> {
+        fn flag(value: &str) -> Result<bool, String> {
+            match value.to_lowercase().as_str() {
+                "si" | "sí" | "yes" | "1" => Ok(true),
+                "no" | "0" | "" => Ok(false),
+                other => Err(format!("Valor no válido: '{}' (usa si/no)", other)),
             }
         }
-        Err(e) => {
-            error!("Invalid image URL '{}' for movie '{}': {:?}. Sending text message instead.", movie.large_cover_image, movie.title_long, e);
-            let fallback_caption = format!(
-                "Nueva Película: {}\nAño: {}\nCalidad: {}\nCover (URL inválida): {}\nMagnet: {}\nTorrent URL: {}",
-                movie.title_long, movie.year, torrent.quality, movie.large_cover_image, magnet_link, torrent.url
-            );
-            bot.send_message(chat_id, fallback_caption).await?;
+        for arg in args {
+            let (key, value) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("Argumento no reconocido: '{}'", arg))?;
+            match key.to_lowercase().as_str() {
+                "calidades" | "calidad" => {
+                    self.qualities = value.split(',').map(str::trim).filter(|v| !v.is_empty()).map(ToOwned::to_owned).collect()
+                }
+                "fuente" => {
+                    self.source = match value.to_lowercase().as_str() {
+                        "" => None,
+                        source @ ("bluray" | "web") => Some(source.to_string()),
+                        other => return Err(format!("Fuente no válida: '{}' (usa bluray o web)", other)),
+                    }
+                }
+                "x265" => self.prefer_x265 = flag(value)?,
+                "seeds" => self.most_seeds = flag(value)?,
+                "max" => {
+                    self.max_size_bytes = if value.is_empty() {
+                        None
+                    } else {
+                        Some(parse_size(value).ok_or_else(|| format!("Tamaño no válido: '{}' (ej. 4GB o 1500MB)", value))?)
+                    }
+                }
+                other => return Err(format!("Preferencia desconocida: '{}'", other)),
+            }
         }
+        Ok(())
     }
-    Ok(())
-}
 
-// --- YTS Command Handlers ---
+    fn is_empty(&self) -> bool {
+        self.qualities.is_empty() && self.source.is_none() && !self.prefer_x265 && !self.most_seeds && self.max_size_bytes.is_none()
+    }
 
-/// Command `/yts_init`: Subscribes the chat to YTS movie notifications and starts the monitor if not running.
-async fn yts_init_command(bot: Bot, msg: Message) -> ResponseResult<()> {
-    let chat_id = msg.chat.id;
+    fn describe(&self) -> String {
+        if self.is_empty() {
+            return "sin política (se muestran todos los torrents)".to_string();
+        }
+        let mut parts = Vec::new();
+        if !self.qualities.is_empty() {
+            parts.push(format!("calidades={}", self.qualities.join(">")));
+        }
+        if let Some(source) = &self.source {
+            parts.push(format!("fuente={}", source));
+        }
+        if self.prefer_x265 {
+            parts.push("x265=si".to_string());
+        }
+        if self.most_seeds {
+            parts.push("seeds=si".to_string());
+        }
+        if let Some(max) = self.max_size_bytes {
+            parts.push(format!("max={:.1}GB", max as f64 / 1_073_741_824.0));
+        }
+        parts.join(" ")
+    }
+}
+
+/// Parses sizes like "4GB", "1.5 GB" or "700MB" into bytes.
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim().to_uppercase();
+    let (number, multiplier) = if let Some(n) = value.strip_suffix("GB") {
+        (n, 1_073_741_824.0)
+    } else if let Some(n) = value.strip_suffix("MB") {
+        (n, 1_048_576.0)
+    } else {
+        return None;
+    };
+    number.trim().parse::<f64>().ok().filter(|n| *n > 0.0).map(|n| (n * multiplier) as u64)
+}
+
+// Stores the ID of the latest movie processed globally by the bot
+static LAST_PROCESSED_YTS_MOVIE_ID: Lazy<Arc<Mutex<Option<u32>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(load_json_state::<YtsPersistedState>(YTS_STATE_FILE).last_processed_movie_id)));
+// Stores chat IDs that are subscribed to YTS notifications
+static SUBSCRIBED_CHAT_IDS: Lazy<Arc<Mutex<Vec<ChatId>>>> = Lazy::new(|| {
+    let state = load_json_state::<YtsPersistedState>(YTS_STATE_FILE);
+    Arc::new(Mutex::new(state.subscribed_chat_ids.into_iter().map(ChatId).collect()))
+});
+// Stores the notification filters of each subscribed chat
+static YTS_CHAT_FILTERS: Lazy<Arc<Mutex<HashMap<ChatId, YtsFilters>>>> = Lazy::new(|| {
+    let state = load_json_state::<YtsPersistedState>(YTS_STATE_FILE);
+    Arc::new(Mutex::new(state.chat_filters.into_iter().map(|(id, f)| (ChatId(id), f)).collect()))
+});
+// Stores the torrent selection policy of each subscribed chat
+static YTS_CHAT_POLICIES: Lazy<Arc<Mutex<HashMap<ChatId, TorrentPolicy>>>> = Lazy::new(|| {
+    let state = load_json_state::<YtsPersistedState>(YTS_STATE_FILE);
+    Arc::new(Mutex::new(state.chat_policies.into_iter().map(|(id, p)| (ChatId(id), p)).collect()))
+});
+// Stores the torrent hashes of the most recent movies (see YTS_KNOWN_TORRENTS_WINDOW)
+static YTS_KNOWN_TORRENTS: Lazy<Arc<Mutex<KnownTorrents>>> =
+    Lazy::new(|| Arc::new(Mutex::new(load_json_state::<YtsPersistedState>(YTS_STATE_FILE).known_torrents)));
+// Stores the handle of the single YTS monitoring task
+static YTS_MONITOR_TASK_HANDLE: Lazy<Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
+
+const YTS_API_URL: &str = "https://yts.mx/api/v2/list_movies.json";
+const YTS_MOVIE_DETAILS_URL: &str = "https://yts.mx/api/v2/movie_details.json";
+const YTS_MOVIE_SUGGESTIONS_URL: &str = "https://yts.mx/api/v2/movie_suggestions.json";
+const YTS_CARD_MAX_CAST: usize = 5;
+// Callback data of the per-quality buttons: "yts:<movie_id>:<torrent hash>" (well under Telegram's 64 bytes)
+const YTS_TORRENT_CALLBACK_PREFIX: &str = "yts:";
+// Search result buttons: "ytsp:<search_id>:<page>" turns the page, "ytsd:<movie_id>" opens the details
+const YTS_SEARCH_PAGE_CALLBACK_PREFIX: &str = "ytsp:";
+const YTS_DETAILS_CALLBACK_PREFIX: &str = "ytsd:";
+const YTS_SEARCH_PAGE_SIZE: u32 = 5;
+// Searches are kept in memory so page buttons only carry an id; the oldest are dropped past this limit
+const YTS_SEARCH_CACHE_LIMIT: usize = 200;
+const YTS_SORT_FIELDS: [&str; 8] = ["title", "year", "rating", "peers", "seeds", "download_count", "like_count", "date_added"];
+const YTS_CHECK_INTERVAL_SECONDS: u64 = 180; // Check every 2.5 minutes
+const YTS_MOVIES_FETCH_LIMIT: u8 = 10; // Page size when checking for new movies
+// After downtime the monitor pages back to the cursor, but never past this many movies per tick
+const YTS_CATCHUP_MAX_MOVIES_ENV: &str = "YTS_CATCHUP_MAX_MOVIES";
+const DEFAULT_YTS_CATCHUP_MAX_MOVIES: usize = 200;
+static YTS_CATCHUP_MAX_MOVIES: Lazy<usize> = Lazy::new(|| {
+    std::env::var(YTS_CATCHUP_MAX_MOVIES_ENV)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_YTS_CATCHUP_MAX_MOVIES)
+});
+const YTS_KNOWN_TORRENTS_WINDOW: usize = 300; // Movies whose torrent hashes are remembered
+// Adding a torrent doesn't move a movie up in list_movies, so every tick also re-reads this many
+// remembered movies through movie_details, cycling through the window (300 / 10 ticks of 3 min ≈ 90 min)
+const YTS_QUALITY_SWEEP_PER_TICK: usize = 10;
+
+// --- Helper Functions for YTS ---
+
+// Serializes writers of yts_state.json (the YTS state lives behind several mutexes)
+static YTS_SAVE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Writes the current subscriptions, filters, policies, known torrents and cursor to disk. Locks are taken one at a time.
+async fn save_yts_state() {
+    // Held across snapshot and write so concurrent saves can't reorder or interleave on disk
+    let _save_guard = YTS_SAVE_LOCK.lock().await;
+    let subscribed_chat_ids = SUBSCRIBED_CHAT_IDS.lock().await.iter().map(|id| id.0).collect();
+    let last_processed_movie_id = *LAST_PROCESSED_YTS_MOVIE_ID.lock().await;
+    let chat_filters = YTS_CHAT_FILTERS.lock().await.iter().map(|(id, f)| (id.0, f.clone())).collect();
+    let chat_policies = YTS_CHAT_POLICIES.lock().await.iter().map(|(id, p)| (id.0, p.clone())).collect();
+    let known_torrents = YTS_KNOWN_TORRENTS.lock().await.clone();
+    save_json_state(
+        YTS_STATE_FILE,
+        &YtsPersistedState { subscribed_chat_ids, last_processed_movie_id, chat_filters, chat_policies, known_torrents },
+    );
+}
+
+/// Records the torrent hashes of the fetched movies and returns the already-announced movies
+/// (id <= `cursor`) that gained torrents, with the new hashes. The bool tells whether anything changed.
+/// Movies seen for the first time only set a baseline.
+async fn record_known_torrents(movies: &[YtsMovie], cursor: u32) -> (Vec<(YtsMovie, Vec<String>)>, bool) {
+    let mut known = YTS_KNOWN_TORRENTS.lock().await;
+    let mut updates = Vec::new();
+    let mut changed = false;
+    for movie in movies {
+        let hashes = movie.torrents.iter().map(|t| t.hash.to_uppercase());
+        match known.get_mut(&movie.id) {
+            Some(seen) => {
+                let new_hashes = hashes.filter(|h| !seen.contains(h)).collect::<Vec<_>>();
+                if !new_hashes.is_empty() {
+                    seen.extend(new_hashes.iter().cloned());
+                    changed = true;
+                    if movie.id <= cursor {
+                        updates.push((movie.clone(), new_hashes));
+                    }
+                }
+            }
+            None => {
+                known.insert(movie.id, hashes.collect());
+                changed = true;
+            }
+        }
+    }
+    while known.len() > YTS_KNOWN_TORRENTS_WINDOW {
+        let oldest = *known.keys().min().expect("window is not empty");
+        known.remove(&oldest);
+    }
+    (updates, changed)
+}
+
+// Last movie id re-read by the quality sweep; the next tick continues after it
+static YTS_QUALITY_SWEEP_CURSOR: Lazy<Mutex<u32>> = Lazy::new(|| Mutex::new(0));
+
+/// Re-reads the next `YTS_QUALITY_SWEEP_PER_TICK` remembered movies (wrapping around the window)
+/// and records their torrents, returning the same as `record_known_torrents`.
+async fn sweep_known_torrents(cursor: u32) -> (Vec<(YtsMovie, Vec<String>)>, bool) {
+    let ids = {
+        let mut ids = YTS_KNOWN_TORRENTS.lock().await.keys().copied().filter(|id| *id <= cursor).collect::<Vec<_>>();
+        ids.sort_unstable();
+        let mut sweep_cursor = YTS_QUALITY_SWEEP_CURSOR.lock().await;
+        let start = ids.iter().position(|id| *id > *sweep_cursor).unwrap_or(0);
+        let batch = ids.iter().cycle().skip(start).take(YTS_QUALITY_SWEEP_PER_TICK.min(ids.len())).copied().collect::<Vec<_>>();
+        if let Some(last) = batch.last() {
+            *sweep_cursor = *last;
+        }
+        batch
+    };
+    let mut movies = Vec::new();
+    for id in ids {
+        match fetch_yts_movie_details(id).await {
+            Ok(Some(movie)) => movies.push(movie),
+            Ok(None) => debug!("La película {} ya no está en YTS", id),
+            Err(e) => error!("Error releyendo la película {} de YTS: {:?}", id, e),
+        }
+    }
+    record_known_torrents(&movies, cursor).await
+}
+
+/// Announces torrents added to already-announced movies, honouring each chat's filters and policy.
+async fn broadcast_quality_updates(bot: &Bot, updates: &[(YtsMovie, Vec<String>)]) {
+    let chat_ids = SUBSCRIBED_CHAT_IDS.lock().await.clone();
+    let chat_filters = YTS_CHAT_FILTERS.lock().await.clone();
+    let chat_policies = YTS_CHAT_POLICIES.lock().await.clone();
+    for (movie, new_hashes) in updates {
+        info!("Nuevas calidades para '{}' (ID: {}): {}", movie.title_long, movie.id, new_hashes.join(", "));
+        let is_new = |t: &YtsTorrent| new_hashes.iter().any(|h| h.eq_ignore_ascii_case(&t.hash));
+        for &chat_id in &chat_ids {
+            let Some(mut movie_for_chat) = chat_filters.get(&chat_id).map_or(Some(movie.clone()), |f| f.apply(movie)) else {
+                continue;
+            };
+            // With a policy, only announce if the new torrent is now the one the chat would pick
+            if let Some(policy) = chat_policies.get(&chat_id) {
+                match policy.select(&movie_for_chat.torrents) {
+                    Some(chosen) if is_new(chosen) => movie_for_chat.torrents = vec![chosen.clone()],
+                    _ => continue,
+                }
+            }
+            movie_for_chat.torrents.retain(|t| is_new(t));
+            if movie_for_chat.torrents.is_empty() {
+                continue;
+            }
+            let header = format!("🆕 Nueva calidad disponible: {}", movie.title_long);
+            let keyboard = movie_torrent_keyboard(&movie_for_chat);
+            if let Err(e) = send_movie_message(bot, chat_id, &header, &movie_for_chat, keyboard).await {
+                error!("Error enviando nueva calidad de '{}' a chat {}: {:?}", movie.title_long, chat_id, e);
+            }
+        }
+    }
+}
+
+/// Fetches one page of the latest movies from the YTS API.
+async fn fetch_latest_yts_movies(limit: u8, page: u32) -> Result<Vec<YtsMovie>, reqwest::Error> {
+    // API sorts by date_added desc by default
+    let url = format!("{}?sort_by=date_added&order_by=desc&limit={}&page={}", YTS_API_URL, limit, page);
+    debug!("Fetching YTS movies from URL: {}", url);
+    let response = reqwest::get(&url).await?.json::<YtsApiResponse>().await?;
+
+    if response.status == "ok" {
+        if let Some(data) = response.data {
+            if let Some(movies) = data.movies {
+                return Ok(movies);
+            }
+        }
+    }
+    // If status is not "ok" or data/movies are missing, return empty or an error
+    error!("Failed to fetch movies or parse response: Status: {}, Message: {}", response.status, response.status_message);
+    Ok(Vec::new())
+}
+
+/// Fetches the latest movies page by page until reaching one already processed (id <= `cursor`),
+/// so uploads between ticks or during downtime are not skipped. Stops at YTS_CATCHUP_MAX_MOVIES;
+/// the bool tells whether that cap cut the catch-up short.
+async fn fetch_yts_movies_since(cursor: u32) -> Result<(Vec<YtsMovie>, bool), reqwest::Error> {
+    let mut movies: Vec<YtsMovie> = Vec::new();
+    let mut page = 1;
+    loop {
+        let batch = fetch_latest_yts_movies(YTS_MOVIES_FETCH_LIMIT, page).await?;
+        let last_page = batch.len() < YTS_MOVIES_FETCH_LIMIT as usize;
+        let reached_cursor = batch.iter().any(|m| m.id <= cursor);
+        // Uploads during paging shift the pages, which can repeat movies
+        for movie in batch {
+            if !movies.iter().any(|m| m.id == movie.id) {
+                movies.push(movie);
+            }
+        }
+        // Without a cursor there is nothing to catch up to
+        if reached_cursor || last_page || cursor == 0 {
+            return Ok((movies, false));
+        }
+        if movies.len() >= *YTS_CATCHUP_MAX_MOVIES {
+            return Ok((movies, true));
+        }
+        page += 1;
+    }
+}
+
+/// Sends a message to every admin (BOT_ADMIN_IDS) in private.
+async fn notify_admins(bot: &Bot, text: &str) {
+    for &admin in ADMIN_IDS.iter() {
+        if let Err(e) = bot.send_message(ChatId(admin as i64), text).await {
+            error!("No se pudo avisar al administrador {}: {:?}", admin, e);
+        }
+    }
+}
+
+/// Calls the YTS movie_details endpoint with the given query parameters.
+async fn fetch_yts_movie(params: &[(&str, String)]) -> Result<Option<YtsMovie>, reqwest::Error> {
+    let url = Url::parse_with_params(YTS_MOVIE_DETAILS_URL, params).expect("YTS_MOVIE_DETAILS_URL is a valid URL");
+    debug!("Fetching YTS movie details from URL: {}", url);
+    let response = reqwest::get(url).await?.json::<YtsApiResponse>().await?;
+    if response.status != "ok" {
+        error!("Failed to fetch movie {:?}: Status: {}, Message: {}", params, response.status, response.status_message);
+        return Ok(None);
+    }
+    Ok(response.data.and_then(|data| data.movie))
+}
+
+/// Fetches a single movie (with all its torrents) from the YTS movie_details endpoint.
+async fn fetch_yts_movie_details(movie_id: u32) -> Result<Option<YtsMovie>, reqwest::Error> {
+    // YTS answers unknown ids with an empty movie (id 0) instead of an error
+    Ok(fetch_yts_movie(&[("movie_id", movie_id.to_string())]).await?.filter(|movie| movie.id == movie_id))
+}
+
+/// Fetches a movie with images and cast by YTS id or IMDb code (tt...), for the details card.
+async fn fetch_yts_movie_card(key: &str) -> Result<Option<YtsMovie>, reqwest::Error> {
+    let key = key.trim();
+    let lookup = if key.starts_with("tt") {
+        ("imdb_id", key.to_string())
+    } else {
+        match key.parse::<u32>() {
+            Ok(movie_id) => ("movie_id", movie_id.to_string()),
+            Err(_) => return Ok(None),
+        }
+    };
+    let params = [lookup.clone(), ("with_images", "true".to_string()), ("with_cast", "true".to_string())];
+    let movie = fetch_yts_movie(&params).await?;
+    Ok(movie.filter(|m| m.id.to_string() == lookup.1 || m.imdb_code.eq_ignore_ascii_case(&lookup.1)))
+}
+
+/// Fetches the related titles YTS suggests for a movie.
+async fn fetch_yts_movie_suggestions(movie_id: u32) -> Result<Vec<YtsMovie>, reqwest::Error> {
+    let url = format!("{}?movie_id={}", YTS_MOVIE_SUGGESTIONS_URL, movie_id);
+    debug!("Fetching YTS suggestions from URL: {}", url);
+    let response = reqwest::get(&url).await?.json::<YtsApiResponse>().await?;
+    Ok(response.data.and_then(|data| data.movies).unwrap_or_default())
+}
+
+/// Constructs a magnet link for a given torrent hash and movie name.
+fn construct_magnet_link(hash: &str, movie_name: &str) -> String {
+    let encoded_movie_name = urlencoding::encode(movie_name);
+    // Trackers recommended by YTS API documentation
+    let trackers = [
+        "udp://open.demonii.com:1337/announce",
+        "udp://tracker.openbittorrent.com:80",
+        "udp://tracker.coppersurfer.tk:6969",
+        "udp://glotorrents.pw:6969/announce",
+        "udp://tracker.opentrackr.org:1337/announce",
+        "udp://torrent.gresille.org:80/announce",
+        "udp://p4p.arenabg.com:1337",
+        "udp://tracker.leechers-paradise.org:6969",
+    ];
+    let tracker_params = trackers.iter().map(|t| format!("&tr={}", urlencoding::encode(t))).collect::<String>();
+    format!("`magnet:?xt=urn:btih:{}&dn={}{}`", hash, encoded_movie_name, tracker_params)
+}
+
+/// Rating, runtime, classification, genres and external links of a movie, one fact per line.
+fn format_movie_details(movie: &YtsMovie) -> String {
+    let mut facts = Vec::new();
+    if movie.rating > 0.0 {
+        facts.push(format!("⭐ {:.1}/10", movie.rating));
+    }
+    if movie.runtime > 0 {
+        facts.push(format!("{}h {:02}m", movie.runtime / 60, movie.runtime % 60));
+    }
+    if !movie.mpa_rating.is_empty() {
+        facts.push(movie.mpa_rating.clone());
+    }
+    if !movie.language.is_empty() {
+        facts.push(movie.language.to_uppercase());
+    }
+    let mut lines = Vec::new();
+    if !facts.is_empty() {
+        lines.push(facts.join(" · "));
+    }
+    if !movie.genres.is_empty() {
+        lines.push(format!("Géneros: {}", movie.genres.join(", ")));
+    }
+    if movie.date_uploaded_unix > 0 {
+        lines.push(format!("Subida: {}", format_timestamp(movie.date_uploaded_unix)));
+    }
+    if !movie.cast.is_empty() {
+        let cast = movie
+            .cast
+            .iter()
+            .take(YTS_CARD_MAX_CAST)
+            .map(|c| if c.character_name.is_empty() { c.name.clone() } else { format!("{} ({})", c.name, c.character_name) })
+            .collect::<Vec<_>>();
+        lines.push(format!("Reparto: {}", cast.join(", ")));
+    }
+    if let Some(url) = imdb_url(movie) {
+        lines.push(format!("IMDb: {}", url));
+    }
+    if let Some(url) = trailer_url(movie) {
+        lines.push(format!("Tráiler: {}", url));
+    }
+    lines.join("\n")
+}
+
+fn imdb_url(movie: &YtsMovie) -> Option<String> {
+    (!movie.imdb_code.is_empty()).then(|| format!("https://www.imdb.com/title/{}/", movie.imdb_code))
+}
+
+fn trailer_url(movie: &YtsMovie) -> Option<String> {
+    (!movie.yt_trailer_code.is_empty()).then(|| format!("https://www.youtube.com/watch?v={}", movie.yt_trailer_code))
+}
+
+/// Quality, release type and codec of a torrent, e.g. "1080p bluray x265".
+fn torrent_label(torrent: &YtsTorrent) -> String {
+    [torrent.quality.as_str(), torrent.torrent_type.as_str(), torrent.video_codec.as_str()]
+        .iter()
+        .filter(|part| !part.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Label, size and swarm of a torrent, e.g. "1080p bluray x265 · 1.9 GB · 🌱 120 / 👥 30".
+fn format_torrent_summary(torrent: &YtsTorrent) -> String {
+    let mut summary = torrent_label(torrent);
+    if !torrent.size.is_empty() {
+        summary.push_str(&format!(" · {}", torrent.size));
+    }
+    summary.push_str(&format!(" · 🌱 {} / 👥 {}", torrent.seeds, torrent.peers));
+    summary
+}
+
+/// Builds the notification text listing every torrent, trimming the synopsis so the whole text fits in `limit` characters.
+fn format_movie_caption(header: &str, movie: &YtsMovie, limit: usize) -> String {
+    let head = format!("{}\nAño: {}\n{}", header, movie.year, format_movie_details(movie));
+    let torrents = movie.torrents.iter().map(|t| format!("\n• {}", format_torrent_summary(t))).collect::<String>();
+    let tail = format!("\n\nTorrents:{}\n\nPulsa una calidad para recibir su magnet y el .torrent.", torrents);
+    let budget = limit.saturating_sub(head.chars().count() + tail.chars().count() + 2);
+    let summary = if movie.summary.trim().is_empty() { movie.description_full.trim() } else { movie.summary.trim() };
+    let synopsis = if summary.is_empty() || budget < 40 {
+        String::new()
+    } else if summary.chars().count() > budget {
+        format!("\n\n{}…", summary.chars().take(budget - 1).collect::<String>())
+    } else {
+        format!("\n\n{}", summary)
+    };
+    let caption = format!("{}{}{}", head, synopsis, tail);
+    if caption.chars().count() > limit {
+        caption.chars().take(limit - 1).collect::<String>() + "…"
+    } else {
+        caption
+    }
+}
+
+/// One button per torrent; pressing it sends that version's magnet link and .torrent URL.
+fn movie_torrent_keyboard(movie: &YtsMovie) -> InlineKeyboardMarkup {
+    let buttons = movie
+        .torrents
+        .iter()
+        .map(|t| {
+            InlineKeyboardButton::callback(
+                format!("🧲 {}", torrent_label(t)),
+                format!("{}{}:{}", YTS_TORRENT_CALLBACK_PREFIX, movie.id, t.hash),
+            )
+        })
+        .collect::<Vec<_>>();
+    InlineKeyboardMarkup::new(buttons.chunks(2).map(<[_]>::to_vec))
+}
+
+/// Sends a movie notification (photo with caption and one button per torrent) to a specified chat.
+/// With a selection policy only the torrent it picks is announced.
+async fn send_movie_notification(bot: &Bot, chat_id: ChatId, movie: &YtsMovie, policy: Option<&TorrentPolicy>) -> ResponseResult<()> {
+    if movie.torrents.is_empty() {
+        info!("Movie '{}' (ID: {}) has no torrents, skipping notification.", movie.title_long, movie.id);
+        return Ok(());
+    }
+    let selected;
+    let movie = match policy {
+        Some(policy) => match policy.select(&movie.torrents) {
+            Some(torrent) => {
+                selected = YtsMovie { torrents: vec![torrent.clone()], ..movie.clone() };
+                &selected
+            }
+            None => {
+                info!("Ningún torrent de '{}' cumple la política del chat {}; no se anuncia.", movie.title_long, chat_id);
+                return Ok(());
+            }
+        },
+        None => movie,
+    };
+
+    let header = format!("Nueva Película: {}", movie.title_long);
+    send_movie_message(bot, chat_id, &header, movie, movie_torrent_keyboard(movie)).await
+}
+
+/// Sends the cover with the movie caption (starting with `header`) and keyboard, falling back to a text message if the photo fails.
+async fn send_movie_message(bot: &Bot, chat_id: ChatId, header: &str, movie: &YtsMovie, keyboard: InlineKeyboardMarkup) -> ResponseResult<()> {
+    let caption = format_movie_caption(header, movie, TELEGRAM_CAPTION_LIMIT);
+    
+    info!("Preparing to send notification for '{}' to chat {}", movie.title_long, chat_id);
+
+    match Url::parse(&movie.large_cover_image) {
+        Ok(img_url) => {
+            match bot.send_photo(chat_id, InputFile::url(img_url)).caption(caption).reply_markup(keyboard.clone()).await {
+                Ok(_) => info!("Notification sent successfully for '{}' to chat {}", movie.title_long, chat_id),
+                Err(e) => {
+                    error!("Failed to send photo notification for '{}' to chat {}: {:?}. Trying text message.", movie.title_long, chat_id, e);
+                    // Fallback to text message if photo send fails (e.g. image too big, bot blocked by user etc.)
+                    let header = format!("{}\nCover: {}", header, movie.large_cover_image);
+                    let fallback_caption = format_movie_caption(&header, movie, TELEGRAM_MESSAGE_LIMIT);
+                    bot.send_message(chat_id, fallback_caption).reply_markup(keyboard).await?;
+                }
+            }
+        }
+        Err(e) => {
+            error!("Invalid image URL '{}' for movie '{}': {:?}. Sending text message instead.", movie.large_cover_image, movie.title_long, e);
+            let header = format!("{}\nCover (URL inválida): {}", header, movie.large_cover_image);
+            let fallback_caption = format_movie_caption(&header, movie, TELEGRAM_MESSAGE_LIMIT);
+            bot.send_message(chat_id, fallback_caption).reply_markup(keyboard).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends the detailed card of a movie: torrent buttons, trailer/IMDb links and related titles.
+async fn send_movie_card(bot: &Bot, chat_id: ChatId, movie: &YtsMovie) -> ResponseResult<()> {
+    let mut rows = movie_torrent_keyboard(movie).inline_keyboard;
+    let links = [("🎬 Tráiler", trailer_url(movie)), ("⭐ IMDb", imdb_url(movie))]
+        .into_iter()
+        .filter_map(|(label, url)| Some(InlineKeyboardButton::url(label, Url::parse(&url?).ok()?)))
+        .collect::<Vec<_>>();
+    if !links.is_empty() {
+        rows.push(links);
+    }
+    match fetch_yts_movie_suggestions(movie.id).await {
+        Ok(suggestions) => {
+            for suggestion in suggestions.iter().filter(|s| s.id != movie.id) {
+                let label = suggestion.title_long.chars().take(40).collect::<String>();
+                rows.push(vec![InlineKeyboardButton::callback(
+                    format!("🔗 {}", label),
+                    format!("{}{}", YTS_DETAILS_CALLBACK_PREFIX, suggestion.id),
+                )]);
+            }
+        }
+        Err(e) => error!("Error obteniendo sugerencias de YTS para {}: {:?}", movie.id, e),
+    }
+    send_movie_message(bot, chat_id, &movie.title_long, movie, InlineKeyboardMarkup::new(rows)).await?;
+
+    // Screenshots go in an album under the card; failing to send them doesn't fail the card
+    let screenshots = [&movie.large_screenshot_image1, &movie.large_screenshot_image2, &movie.large_screenshot_image3]
+        .into_iter()
+        .filter_map(|url| Url::parse(url).ok())
+        .map(|url| InputMedia::Photo(InputMediaPhoto::new(InputFile::url(url))))
+        .collect::<Vec<_>>();
+    if !screenshots.is_empty() {
+        if let Err(e) = bot.send_media_group(chat_id, screenshots).await {
+            error!("No se pudieron enviar las capturas de '{}' a chat {}: {:?}", movie.title_long, chat_id, e);
+        }
+    }
+    Ok(())
+}
+
+/// Command `/yts_movie <id|imdb>`: shows the detailed card of a single movie.
+async fn yts_movie_command(bot: Bot, msg: Message, key: String) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    match fetch_yts_movie_card(&key).await {
+        Ok(Some(movie)) => send_movie_card(&bot, chat_id, &movie).await,
+        Ok(None) => {
+            bot.send_message(chat_id, format!("ℹ️ No hay ninguna película en YTS con id o código IMDb '{}'.", key)).await?;
+            Ok(())
+        }
+        Err(e) => {
+            error!("Error obteniendo la película '{}' de YTS: {:?}", key, e);
+            bot.send_message(chat_id, "⚠️ No se pudo consultar YTS. Inténtalo más tarde.").await?;
+            Ok(())
+        }
+    }
+}
+
+/// Handles a per-quality button: looks the movie up again and replies with that torrent's magnet and .torrent URL.
+async fn yts_torrent_callback(bot: Bot, q: CallbackQuery, payload: &str) -> ResponseResult<()> {
+    let Some(chat_id) = q.message.as_ref().map(|m| m.chat.id) else {
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+    let parsed = payload.split_once(':').and_then(|(id, hash)| id.parse::<u32>().ok().map(|id| (id, hash)));
+    let Some((movie_id, hash)) = parsed else {
+        bot.answer_callback_query(q.id).text("Botón no válido.").await?;
+        return Ok(());
+    };
+    let movie = match fetch_yts_movie_details(movie_id).await {
+        Ok(movie) => movie,
+        Err(e) => {
+            error!("Error obteniendo la película {} de YTS: {:?}", movie_id, e);
+            None
+        }
+    };
+    let found = movie.as_ref().and_then(|m| m.torrents.iter().find(|t| t.hash.eq_ignore_ascii_case(hash)).map(|t| (m, t)));
+    let Some((movie, torrent)) = found else {
+        bot.answer_callback_query(q.id).text("⚠️ Ese torrent ya no está disponible en YTS.").show_alert(true).await?;
+        return Ok(());
+    };
+    bot.answer_callback_query(q.id).await?;
+    let text = format!(
+        "🧲 {}\n{}\n\nMagnet: {}\n\nTorrent URL: {}",
+        movie.title_long,
+        format_torrent_summary(torrent),
+        construct_magnet_link(&torrent.hash, &movie.title_long),
+        torrent.url
+    );
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+// --- YTS Command Handlers ---
+
+/// Command `/yts_init`: Subscribes the chat to YTS movie notifications and starts the monitor if not running.
+async fn yts_init_command(bot: Bot, msg: Message) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
     {
         let mut chats = SUBSCRIBED_CHAT_IDS.lock().await;
         if !chats.contains(&chat_id) {
             chats.push(chat_id);
+            drop(chats);
+            save_yts_state().await;
             bot.send_message(chat_id, "✅ Te has suscrito a las notificaciones de nuevas películas de YTS.").await?;
             info!("Chat {} suscrito a notificaciones YTS.", chat_id);
         } else {
@@ -176,8 +1536,17 @@ async fn yts_init_command(bot: Bot, msg: Message) -> ResponseResult<()> {
     }
 
     // Ensure the global monitoring task is running
+    if !ensure_yts_monitor_running(&bot).await {
+         // Optionally, notify the user again that they are subscribed and monitoring is active.
+         bot.send_message(chat_id, "ℹ️ El monitor de YTS ya está activo y estás suscrito.").await?;
+    }
+    Ok(())
+}
+
+/// Starts the global YTS monitoring task unless it is already running. Returns true if it was started.
+async fn ensure_yts_monitor_running(bot: &Bot) -> bool {
     let mut task_handle_guard = YTS_MONITOR_TASK_HANDLE.lock().await;
-    if task_handle_guard.is_none() || task_handle_guard.as_ref().map_or(false, |h| h.is_finished()) {
+    if task_handle_guard.as_ref().is_none_or(|h| h.is_finished()) {
         info!("Iniciando tarea de monitorización de YTS...");
         let bot_clone = bot.clone(); // Clone bot for the spawned task
         let last_id_global_clone = Arc::clone(&LAST_PROCESSED_YTS_MOVIE_ID);
@@ -189,10 +1558,12 @@ async fn yts_init_command(bot: Bot, msg: Message) -> ResponseResult<()> {
             {
                 let mut last_id_lock = last_id_global_clone.lock().await;
                 if last_id_lock.is_none() { // Only if no baseline exists at all (e.g. first ever run)
-                    match fetch_latest_yts_movies(1).await {
+                    match fetch_latest_yts_movies(1, 1).await {
                         Ok(movies) if !movies.is_empty() => {
                             *last_id_lock = Some(movies[0].id);
                             info!("Línea base inicial de YTS (LAST_PROCESSED_YTS_MOVIE_ID) establecida en ID: {}", movies[0].id);
+                            drop(last_id_lock);
+                            save_yts_state().await;
                         }
                         Ok(_) => info!("No se encontraron películas para establecer la línea base inicial."),
                         Err(e) => error!("Error al obtener películas para la línea base inicial: {:?}", e),
@@ -207,14 +1578,18 @@ async fn yts_init_command(bot: Bot, msg: Message) -> ResponseResult<()> {
 
                 let mut movies_to_broadcast_this_tick: Vec<YtsMovie> = Vec::new();
                 let mut new_highest_id_processed_this_tick = None;
+                let mut quality_updates_this_tick;
+                let mut known_torrents_changed;
+                let catch_up_truncated;
 
                 { // Scope for locking LAST_PROCESSED_YTS_MOVIE_ID
                     // Removed `mut` as per warning, guard itself is not mutated here.
                     let global_last_processed_id_guard = last_id_global_clone.lock().await;
                     let current_global_last_id = global_last_processed_id_guard.unwrap_or(0);
 
-                    match fetch_latest_yts_movies(YTS_MOVIES_FETCH_LIMIT).await {
-                        Ok(fetched_movies) => {
+                    match fetch_yts_movies_since(current_global_last_id).await {
+                        Ok((fetched_movies, truncated)) => {
+                            catch_up_truncated = truncated.then_some(fetched_movies.len());
                             if fetched_movies.is_empty() {
                                 debug!("No se encontraron películas en la comprobación periódica de YTS.");
                                 continue; // Skip to next tick
@@ -238,6 +1613,9 @@ async fn yts_init_command(bot: Bot, msg: Message) -> ResponseResult<()> {
                                 // Reverse so that older new movies are broadcast first
                                 movies_to_broadcast_this_tick = temp_new_movies.into_iter().rev().collect();
                             }
+
+                            (quality_updates_this_tick, known_torrents_changed) =
+                                record_known_torrents(&fetched_movies, current_global_last_id).await;
                         }
                         Err(e) => {
                             error!("Error buscando películas en YTS durante la comprobación periódica: {:?}", e);
@@ -246,17 +1624,39 @@ async fn yts_init_command(bot: Bot, msg: Message) -> ResponseResult<()> {
                     }
                 } // global_last_processed_id_guard is released here
 
+                if let Some(fetched) = catch_up_truncated {
+                    let report = format!(
+                        "⚠️ Recuperación de YTS truncada: se revisaron {} películas (límite {}) sin llegar a la última procesada; las más antiguas no se anunciarán.",
+                        fetched, *YTS_CATCHUP_MAX_MOVIES
+                    );
+                    warn!("{}", report);
+                    notify_admins(&bot_clone, &report).await;
+                }
+
                 if !movies_to_broadcast_this_tick.is_empty() {
                     // Clone the list of subscribed chat IDs to avoid holding the lock while sending messages
                     let chat_ids_to_notify = subscribed_chats_global_clone.lock().await.clone();
+                    let chat_filters = YTS_CHAT_FILTERS.lock().await.clone();
+                    let chat_policies = YTS_CHAT_POLICIES.lock().await.clone();
                     if chat_ids_to_notify.is_empty() {
                         debug!("Hay nuevas películas pero ningún chat suscrito.");
                     }
 
+                    // Watchlists are checked even when no chat is subscribed to the general feed
+                    notify_watchlist_matches(&bot_clone, &movies_to_broadcast_this_tick).await;
+
                     for movie_to_broadcast in movies_to_broadcast_this_tick {
                         info!("Transmitiendo nueva película: '{}' (ID: {})", movie_to_broadcast.title_long, movie_to_broadcast.id);
                         for &target_chat_id in &chat_ids_to_notify {
-                            if let Err(e) = send_movie_notification(&bot_clone, target_chat_id, &movie_to_broadcast).await {
+                            let movie_for_chat = match chat_filters.get(&target_chat_id) {
+                                Some(filters) => filters.apply(&movie_to_broadcast),
+                                None => Some(movie_to_broadcast.clone()),
+                            };
+                            let Some(movie_for_chat) = movie_for_chat else {
+                                debug!("'{}' descartada por los filtros del chat {}", movie_to_broadcast.title_long, target_chat_id);
+                                continue;
+                            };
+                            if let Err(e) = send_movie_notification(&bot_clone, target_chat_id, &movie_for_chat, chat_policies.get(&target_chat_id)).await {
                                 error!("Error enviando notificación de '{}' a chat {}: {:?}", movie_to_broadcast.title_long, target_chat_id, e);
                                 // Consider removing chat_id if bot is blocked or other persistent error
                             }
@@ -271,164 +1671,2568 @@ async fn yts_init_command(bot: Bot, msg: Message) -> ResponseResult<()> {
                         if new_id > global_last_processed_id_guard.unwrap_or(0) {
                            *global_last_processed_id_guard = Some(new_id);
                             info!("Global LAST_PROCESSED_YTS_MOVIE_ID actualizado a: {}", new_id);
+                            drop(global_last_processed_id_guard);
+                            save_yts_state().await;
                         }
                     }
                 } else {
                     debug!("No hay nuevas películas de YTS para transmitir en este ciclo.");
                 }
+
+                let cursor_for_sweep = last_id_global_clone.lock().await.unwrap_or(0);
+                let (swept_updates, swept_changed) = sweep_known_torrents(cursor_for_sweep).await;
+                quality_updates_this_tick.extend(swept_updates);
+                known_torrents_changed |= swept_changed;
+
+                if !quality_updates_this_tick.is_empty() {
+                    broadcast_quality_updates(&bot_clone, &quality_updates_this_tick).await;
+                }
+                if known_torrents_changed {
+                    save_yts_state().await;
+                }
             }
         });
-        *task_handle_guard = Some(new_handle);
-        info!("Tarea de monitorización de YTS iniciada.");
-    } else {
-         info!("La tarea de monitorización de YTS ya está en ejecución.");
-         // Optionally, notify the user again that they are subscribed and monitoring is active.
-         bot.send_message(chat_id, "ℹ️ El monitor de YTS ya está activo y estás suscrito.").await?;
+        *task_handle_guard = Some(new_handle);
+        info!("Tarea de monitorización de YTS iniciada.");
+        true
+    } else {
+         info!("La tarea de monitorización de YTS ya está en ejecución.");
+         false
+    }
+}
+
+/// Command `/yts_stop`: Unsubscribes the chat from YTS movie notifications.
+async fn yts_stop_command(bot: Bot, msg: Message) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let mut unsubscribed = false;
+    {
+        let mut chats = SUBSCRIBED_CHAT_IDS.lock().await;
+        if let Some(pos) = chats.iter().position(|&id| id == chat_id) {
+            chats.remove(pos);
+            unsubscribed = true;
+        }
+    }
+    if unsubscribed {
+        // Filters and policy belong to the subscription and are dropped with it
+        YTS_CHAT_FILTERS.lock().await.remove(&chat_id);
+        YTS_CHAT_POLICIES.lock().await.remove(&chat_id);
+        save_yts_state().await;
+        bot.send_message(chat_id, "✅ Te has dado de baja de las notificaciones de YTS.").await?;
+        info!("Chat {} dado de baja de notificaciones YTS.", chat_id);
+
+        stop_yts_monitor_if_idle().await;
+    } else {
+        bot.send_message(chat_id, "ℹ️ No estabas suscrito a las notificaciones de YTS.").await?;
+    }
+    Ok(())
+}
+
+/// Stops the monitor task once no chat is subscribed and no watchlist entry is pending.
+/// This might be desirable to save resources if the bot has other functions.
+async fn stop_yts_monitor_if_idle() {
+    if !SUBSCRIBED_CHAT_IDS.lock().await.is_empty() || watchlist_has_wants().await {
+        return;
+    }
+    info!("No hay chats suscritos ni deseos pendientes. Intentando detener la tarea de monitorización de YTS...");
+    let mut task_handle_guard = YTS_MONITOR_TASK_HANDLE.lock().await;
+    if let Some(handle) = task_handle_guard.take() { // .take() removes the handle
+        handle.abort(); // Abort the task
+        info!("Tarea de monitorización de YTS detenida ya que no hay suscriptores.");
+         // Notify admin or log, don't message user as they just unsubscribed.
+    }
+}
+
+/// Command `/yts_filter`: Shows, updates or resets the notification filters of a subscribed chat.
+async fn yts_filter_command(bot: Bot, msg: Message, args: Vec<String>) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    if !SUBSCRIBED_CHAT_IDS.lock().await.contains(&chat_id) {
+        bot.send_message(chat_id, "ℹ️ Los filtros se guardan con la suscripción. Suscríbete primero con /yts_init.").await?;
+        return Ok(());
+    }
+    let usage = "⚠️ Uso: /yts_filter [calidad=1080p,2160p] [rating=6.5] [genero=horror,comedy] [año=2000-2024] [idioma=en] [duracion=80-180]\n/yts_filter reset\nUn valor vacío (ej. rating=) elimina ese filtro.";
+    let reply = {
+        let mut filters = YTS_CHAT_FILTERS.lock().await;
+        match args.first().map(String::as_str) {
+            None => format!("🎛️ Filtros YTS de este chat: {}", filters.get(&chat_id).cloned().unwrap_or_default().describe()),
+            Some("reset") => {
+                filters.remove(&chat_id);
+                "✅ Filtros YTS eliminados; se anunciarán todas las películas.".to_string()
+            }
+            Some(_) => {
+                let mut updated = filters.get(&chat_id).cloned().unwrap_or_default();
+                match updated.update(&args) {
+                    Ok(()) => {
+                        let reply = format!("✅ Filtros YTS actualizados: {}", updated.describe());
+                        if updated.is_empty() {
+                            filters.remove(&chat_id);
+                        } else {
+                            filters.insert(chat_id, updated);
+                        }
+                        reply
+                    }
+                    Err(e) => format!("⚠️ {}\n{}", e, usage),
+                }
+            }
+        }
+    };
+    save_yts_state().await;
+    bot.send_message(chat_id, reply).await?;
+    Ok(())
+}
+
+/// Command `/yts_policy`: Shows, updates or resets the torrent selection policy of a subscribed chat.
+async fn yts_policy_command(bot: Bot, msg: Message, args: Vec<String>) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    if !SUBSCRIBED_CHAT_IDS.lock().await.contains(&chat_id) {
+        bot.send_message(chat_id, "ℹ️ La política se guarda con la suscripción. Suscríbete primero con /yts_init.").await?;
+        return Ok(());
+    }
+    let usage = "⚠️ Uso: /yts_policy [calidades=2160p,1080p,720p] [fuente=bluray|web] [x265=si|no] [seeds=si|no] [max=4GB]\n/yts_policy reset\nUn valor vacío (ej. max=) elimina esa preferencia.";
+    let reply = {
+        let mut policies = YTS_CHAT_POLICIES.lock().await;
+        match args.first().map(String::as_str) {
+            None => format!("🎯 Política de torrents de este chat: {}", policies.get(&chat_id).cloned().unwrap_or_default().describe()),
+            Some("reset") => {
+                policies.remove(&chat_id);
+                "✅ Política eliminada; se mostrarán todos los torrents de cada película.".to_string()
+            }
+            Some(_) => {
+                let mut updated = policies.get(&chat_id).cloned().unwrap_or_default();
+                match updated.update(&args) {
+                    Ok(()) => {
+                        let reply = format!("✅ Política actualizada: {}", updated.describe());
+                        if updated.is_empty() {
+                            policies.remove(&chat_id);
+                        } else {
+                            policies.insert(chat_id, updated);
+                        }
+                        reply
+                    }
+                    Err(e) => format!("⚠️ {}\n{}", e, usage),
+                }
+            }
+        }
+    };
+    save_yts_state().await;
+    bot.send_message(chat_id, reply).await?;
+    Ok(())
+}
+
+// --- YTS Search ---
+
+/// A `/yts_search` query and its list_movies parameters.
+#[derive(Debug, Clone, Default)]
+struct YtsSearch {
+    query: String,
+    quality: Option<String>,
+    genre: Option<String>,
+    minimum_rating: Option<u8>,
+    sort_by: Option<String>,
+}
+
+impl YtsSearch {
+    /// Parses `<texto> [calidad=1080p] [genero=horror] [rating=7] [orden=year]`; free words form the query.
+    fn parse(args: &[String]) -> Result<YtsSearch, String> {
+        let mut search = YtsSearch::default();
+        let mut words = Vec::new();
+        for arg in args {
+            let Some((key, value)) = arg.split_once('=') else {
+                words.push(arg.as_str());
+                continue;
+            };
+            match key.to_lowercase().as_str() {
+                "calidad" => search.quality = Some(value.to_string()),
+                "genero" | "género" => search.genre = Some(value.to_string()),
+                "rating" => {
+                    search.minimum_rating = Some(
+                        value.parse().ok().filter(|r| *r <= 9).ok_or_else(|| format!("Rating no válido: '{}' (0-9)", value))?,
+                    )
+                }
+                "orden" => {
+                    if !YTS_SORT_FIELDS.contains(&value) {
+                        return Err(format!("Orden no válido: '{}' (usa {})", value, YTS_SORT_FIELDS.join(", ")));
+                    }
+                    search.sort_by = Some(value.to_string());
+                }
+                other => return Err(format!("Parámetro desconocido: '{}'", other)),
+            }
+        }
+        search.query = words.join(" ");
+        if search.query.is_empty() {
+            return Err("Indica qué buscar.".to_string());
+        }
+        Ok(search)
+    }
+
+    fn describe(&self) -> String {
+        let mut description = format!("'{}'", self.query);
+        if let Some(quality) = &self.quality {
+            description.push_str(&format!(" calidad={}", quality));
+        }
+        if let Some(genre) = &self.genre {
+            description.push_str(&format!(" género={}", genre));
+        }
+        if let Some(rating) = self.minimum_rating {
+            description.push_str(&format!(" rating>={}", rating));
+        }
+        if let Some(sort_by) = &self.sort_by {
+            description.push_str(&format!(" orden={}", sort_by));
+        }
+        description
+    }
+}
+
+static YTS_SEARCHES: Lazy<Mutex<(u64, HashMap<u64, YtsSearch>)>> = Lazy::new(|| Mutex::new((0, HashMap::new())));
+
+/// Runs one page of a search against list_movies. Returns the movies and the total number of matches.
+async fn fetch_yts_search_page(search: &YtsSearch, page: u32) -> Result<(Vec<YtsMovie>, u32), reqwest::Error> {
+    let mut params = vec![
+        ("query_term", search.query.clone()),
+        ("limit", YTS_SEARCH_PAGE_SIZE.to_string()),
+        ("page", page.to_string()),
+    ];
+    if let Some(quality) = &search.quality {
+        params.push(("quality", quality.clone()));
+    }
+    if let Some(genre) = &search.genre {
+        params.push(("genre", genre.clone()));
+    }
+    if let Some(rating) = search.minimum_rating {
+        params.push(("minimum_rating", rating.to_string()));
+    }
+    if let Some(sort_by) = &search.sort_by {
+        params.push(("sort_by", sort_by.clone()));
+    }
+    let url = Url::parse_with_params(YTS_API_URL, &params).expect("YTS_API_URL is a valid URL");
+    debug!("Searching YTS: {}", url);
+    let response = reqwest::get(url).await?.json::<YtsApiResponse>().await?;
+    if response.status != "ok" {
+        error!("YTS search failed: Status: {}, Message: {}", response.status, response.status_message);
+        return Ok((Vec::new(), 0));
+    }
+    Ok(response
+        .data
+        .map(|data| (data.movies.unwrap_or_default(), data.movie_count))
+        .unwrap_or_default())
+}
+
+/// Text and keyboard of one results page: a details button per movie plus previous/next buttons.
+fn format_yts_search_page(search_id: u64, search: &YtsSearch, page: u32, movies: &[YtsMovie], total: u32) -> (String, InlineKeyboardMarkup) {
+    let pages = total.div_ceil(YTS_SEARCH_PAGE_SIZE).max(1);
+    let mut text = format!("🔎 {} — {} películas (página {}/{})\n", search.describe(), total, page, pages);
+    let mut rows = Vec::new();
+    for (i, movie) in movies.iter().enumerate() {
+        let qualities = movie.torrents.iter().map(|t| t.quality.as_str()).collect::<Vec<_>>().join(", ");
+        text.push_str(&format!(
+            "\n{}. {} ⭐ {:.1} · {}",
+            (page - 1) * YTS_SEARCH_PAGE_SIZE + i as u32 + 1,
+            movie.title_long,
+            movie.rating,
+            qualities
+        ));
+        let label = movie.title_long.chars().take(40).collect::<String>();
+        rows.push(vec![InlineKeyboardButton::callback(
+            format!("ℹ️ {}", label),
+            format!("{}{}", YTS_DETAILS_CALLBACK_PREFIX, movie.id),
+        )]);
+    }
+    let mut navigation = Vec::new();
+    if page > 1 {
+        navigation.push(InlineKeyboardButton::callback(
+            "◀️ Anterior",
+            format!("{}{}:{}", YTS_SEARCH_PAGE_CALLBACK_PREFIX, search_id, page - 1),
+        ));
+    }
+    if page < pages {
+        navigation.push(InlineKeyboardButton::callback(
+            "Siguiente ▶️",
+            format!("{}{}:{}", YTS_SEARCH_PAGE_CALLBACK_PREFIX, search_id, page + 1),
+        ));
+    }
+    if !navigation.is_empty() {
+        rows.push(navigation);
+    }
+    (text, InlineKeyboardMarkup::new(rows))
+}
+
+/// Command `/yts_search <texto> [calidad=] [genero=] [rating=] [orden=]`: searches the YTS catalogue.
+async fn yts_search_command(bot: Bot, msg: Message, args: Vec<String>) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let search = match YtsSearch::parse(&args) {
+        Ok(search) => search,
+        Err(e) => {
+            bot.send_message(
+                chat_id,
+                format!("⚠️ {}\nUso: /yts_search <texto> [calidad=1080p] [genero=horror] [rating=7] [orden={}]", e, YTS_SORT_FIELDS.join("|")),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    let (movies, total) = match fetch_yts_search_page(&search, 1).await {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Error buscando en YTS: {:?}", e);
+            bot.send_message(chat_id, "⚠️ No se pudo consultar YTS. Inténtalo más tarde.").await?;
+            return Ok(());
+        }
+    };
+    if movies.is_empty() {
+        bot.send_message(chat_id, format!("ℹ️ Sin resultados en YTS para {}.", search.describe())).await?;
+        return Ok(());
+    }
+    let search_id = {
+        let mut searches = YTS_SEARCHES.lock().await;
+        searches.0 += 1;
+        let search_id = searches.0;
+        searches.1.insert(search_id, search.clone());
+        if searches.1.len() > YTS_SEARCH_CACHE_LIMIT {
+            let oldest = *searches.1.keys().min().expect("cache is not empty");
+            searches.1.remove(&oldest);
+        }
+        search_id
+    };
+    let (text, keyboard) = format_yts_search_page(search_id, &search, 1, &movies, total);
+    bot.send_message(chat_id, text).reply_markup(keyboard).await?;
+    Ok(())
+}
+
+/// Handles the previous/next buttons of a results message by editing it in place.
+async fn yts_search_page_callback(bot: Bot, q: CallbackQuery, payload: &str) -> ResponseResult<()> {
+    let Some(message) = q.message.as_ref() else {
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+    let parsed = payload.split_once(':').and_then(|(id, page)| Some((id.parse::<u64>().ok()?, page.parse::<u32>().ok()?)));
+    let search = match parsed {
+        Some((search_id, page)) if page > 0 => YTS_SEARCHES.lock().await.1.get(&search_id).cloned().map(|s| (search_id, page, s)),
+        _ => None,
+    };
+    let Some((search_id, page, search)) = search else {
+        bot.answer_callback_query(q.id).text("⚠️ Esta búsqueda ha caducado; repítela con /yts_search.").show_alert(true).await?;
+        return Ok(());
+    };
+    match fetch_yts_search_page(&search, page).await {
+        Ok((movies, total)) if !movies.is_empty() => {
+            bot.answer_callback_query(q.id).await?;
+            let (text, keyboard) = format_yts_search_page(search_id, &search, page, &movies, total);
+            bot.edit_message_text(message.chat.id, message.id, text).reply_markup(keyboard).await?;
+        }
+        Ok(_) => {
+            bot.answer_callback_query(q.id).text("ℹ️ No hay más resultados.").await?;
+        }
+        Err(e) => {
+            error!("Error buscando en YTS: {:?}", e);
+            bot.answer_callback_query(q.id).text("⚠️ No se pudo consultar YTS.").await?;
+        }
+    }
+    Ok(())
+}
+
+/// Handles a details button (search results and suggestions): sends the movie's detailed card.
+async fn yts_details_callback(bot: Bot, q: CallbackQuery, payload: &str) -> ResponseResult<()> {
+    let Some(chat_id) = q.message.as_ref().map(|m| m.chat.id) else {
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+    let movie = fetch_yts_movie_card(payload).await.unwrap_or_else(|e| {
+        error!("Error obteniendo la película {} de YTS: {:?}", payload, e);
+        None
+    });
+    let Some(movie) = movie else {
+        bot.answer_callback_query(q.id).text("⚠️ No se pudo obtener la película de YTS.").show_alert(true).await?;
+        return Ok(());
+    };
+    bot.answer_callback_query(q.id).await?;
+    send_movie_card(&bot, chat_id, &movie).await
+}
+
+// --- YTS Watchlist ---
+// Per-chat wanted titles, persisted in data/watchlist.json. New uploads are matched by the YTS monitor
+// and older uploads by a periodic search; a fulfilled want is announced to whoever asked and removed.
+
+const WATCHLIST_FILE: &str = "watchlist.json";
+const WATCHLIST_SEARCH_INTERVAL_SECONDS: u64 = 6 * 3600;
+// Minimum normalized title similarity (0-1) for a fuzzy match
+const WATCHLIST_TITLE_SIMILARITY: f64 = 0.85;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct WantedMovie {
+    id: u64,
+    /// What the user typed, shown in /wants and in the alert.
+    query: String,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    year: Option<u32>,
+    #[serde(default)]
+    imdb_code: Option<String>,
+    #[serde(default)]
+    requested_by: Option<u64>,
+    added_at: i64,
+}
+
+impl WantedMovie {
+    /// Parses `<imdb id>` or `<title> [year]`.
+    fn parse(id: u64, query: &str, requested_by: Option<u64>) -> Option<WantedMovie> {
+        let query = query.trim();
+        if query.is_empty() {
+            return None;
+        }
+        let mut want = WantedMovie {
+            id,
+            query: query.to_string(),
+            title: None,
+            year: None,
+            imdb_code: None,
+            requested_by,
+            added_at: chrono::Utc::now().timestamp(),
+        };
+        if query.len() > 2 && query.starts_with("tt") && query[2..].chars().all(|c| c.is_ascii_digit()) {
+            want.imdb_code = Some(query.to_string());
+            return Some(want);
+        }
+        let mut words = query.split_whitespace().collect::<Vec<_>>();
+        let year = words
+            .last()
+            .map(|w| w.trim_matches(|c| c == '(' || c == ')'))
+            .and_then(|w| w.parse::<u32>().ok())
+            .filter(|y| (1900..=2100).contains(y));
+        if year.is_some() && words.len() > 1 {
+            words.pop();
+            want.year = year;
+        }
+        want.title = Some(words.join(" "));
+        Some(want)
+    }
+
+    fn matches(&self, movie: &YtsMovie) -> bool {
+        if let Some(imdb_code) = &self.imdb_code {
+            return imdb_code.eq_ignore_ascii_case(&movie.imdb_code);
+        }
+        if self.year.is_some_and(|year| year != movie.year) {
+            return false;
+        }
+        let Some(title) = &self.title else {
+            return false;
+        };
+        let movie_title = if movie.title.is_empty() { &movie.title_long } else { &movie.title };
+        title_similarity(title, movie_title) >= WATCHLIST_TITLE_SIMILARITY
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct WatchlistState {
+    next_id: u64,
+    wants: HashMap<i64, Vec<WantedMovie>>,
+}
+
+static WATCHLIST: Lazy<Mutex<WatchlistState>> = Lazy::new(|| Mutex::new(load_json_state(WATCHLIST_FILE)));
+
+async fn watchlist_has_wants() -> bool {
+    WATCHLIST.lock().await.wants.values().any(|w| !w.is_empty())
+}
+
+/// Lowercase alphanumeric words without a leading article, so "The Matrix" and "matrix" compare equal.
+fn normalize_title(title: &str) -> String {
+    let lowered = title.to_lowercase();
+    let words = lowered
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>();
+    let words = match words.first() {
+        Some(&("the" | "a" | "an" | "el" | "la" | "los" | "las")) if words.len() > 1 => &words[1..],
+        _ => &words[..],
+    };
+    words.join(" ")
+}
+
+/// Similarity between two titles in 0-1, from the edit distance of their normalized forms.
+fn title_similarity(a: &str, b: &str) -> f64 {
+    let a = normalize_title(a).chars().collect::<Vec<_>>();
+    let b = normalize_title(b).chars().collect::<Vec<_>>();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 0.0;
+    }
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
+
+/// Tells whoever asked for a title that it is on YTS: privately if possible, otherwise in the chat where they asked.
+async fn notify_wanted_movie(bot: &Bot, chat_id: ChatId, want: &WantedMovie, movie: &YtsMovie) {
+    let text = format!("🎯 ¡Ya está en YTS lo que pediste con /want {}!", want.query);
+    let targets = want.requested_by.map(|user| ChatId(user as i64)).into_iter().chain([chat_id]);
+    for target in targets {
+        let sent = match bot.send_message(target, text.clone()).await {
+            Ok(_) => send_movie_notification(bot, target, movie, None).await,
+            Err(e) => Err(e),
+        };
+        match sent {
+            Ok(()) => {
+                info!("Aviso de lista de deseos '{}' enviado a {}", want.query, target);
+                return;
+            }
+            Err(e) => error!("No se pudo avisar a {} de '{}': {:?}", target, want.query, e),
+        }
+    }
+}
+
+/// Removes and announces the wants matched by any of `movies`.
+async fn notify_watchlist_matches(bot: &Bot, movies: &[YtsMovie]) {
+    let matched = {
+        let mut state = WATCHLIST.lock().await;
+        let mut matched = Vec::new();
+        for (&chat_id, wants) in state.wants.iter_mut() {
+            wants.retain(|want| match movies.iter().find(|movie| want.matches(movie)) {
+                Some(movie) => {
+                    matched.push((ChatId(chat_id), want.clone(), movie.clone()));
+                    false
+                }
+                None => true,
+            });
+        }
+        if !matched.is_empty() {
+            state.wants.retain(|_, wants| !wants.is_empty());
+            save_json_state(WATCHLIST_FILE, &*state);
+        }
+        matched
+    };
+    for (chat_id, want, movie) in matched {
+        notify_wanted_movie(bot, chat_id, &want, &movie).await;
+    }
+}
+
+/// Looks a want up in the whole catalogue, for titles uploaded before it was added.
+async fn search_wanted_movie(want: &WantedMovie) -> Option<YtsMovie> {
+    let query = want.imdb_code.clone().or_else(|| want.title.clone())?;
+    let search = YtsSearch { query, ..YtsSearch::default() };
+    match fetch_yts_search_page(&search, 1).await {
+        Ok((movies, _)) => movies.into_iter().find(|movie| want.matches(movie)),
+        Err(e) => {
+            error!("Error buscando '{}' en YTS para la lista de deseos: {:?}", want.query, e);
+            None
+        }
+    }
+}
+
+/// Periodically searches the catalogue for every pending want.
+async fn run_watchlist_search(bot: Bot) {
+    let mut interval = tokio::time::interval(Duration::from_secs(WATCHLIST_SEARCH_INTERVAL_SECONDS));
+    loop {
+        interval.tick().await;
+        let wants = WATCHLIST.lock().await.wants.values().flatten().cloned().collect::<Vec<_>>();
+        let mut found = Vec::new();
+        for want in &wants {
+            if let Some(movie) = search_wanted_movie(want).await {
+                found.push(movie);
+            }
+        }
+        if !found.is_empty() {
+            notify_watchlist_matches(&bot, &found).await;
+        }
+    }
+}
+
+/// Command `/want <title [year]|imdb id>`: adds a title to the chat's watchlist.
+async fn want_command(bot: Bot, msg: Message, query: String) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let requested_by = msg.from().map(|user| user.id.0);
+    let Some(want) = WantedMovie::parse(0, &query, requested_by) else {
+        bot.send_message(chat_id, "⚠️ Uso: /want <título [año]|id IMDb>. Ejemplo: /want Dune 2021").await?;
+        return Ok(());
+    };
+    // Already on YTS: answer right away instead of waiting for the periodic search
+    if let Some(movie) = search_wanted_movie(&want).await {
+        bot.send_message(chat_id, format!("✅ '{}' ya está en YTS:", want.query)).await?;
+        return send_movie_notification(&bot, chat_id, &movie, None).await;
+    }
+    let id = {
+        let mut state = WATCHLIST.lock().await;
+        state.next_id += 1;
+        let id = state.next_id;
+        state.wants.entry(chat_id.0).or_default().push(WantedMovie { id, ..want.clone() });
+        save_json_state(WATCHLIST_FILE, &*state);
+        id
+    };
+    ensure_yts_monitor_running(&bot).await;
+    bot.send_message(
+        chat_id,
+        format!("👀 Añadido a la lista de deseos (#{}): {}. Te avisaré cuando aparezca en YTS.", id, want.query),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Command `/wants`: lists the chat's pending wants.
+async fn wants_command(bot: Bot, msg: Message) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let wants = WATCHLIST.lock().await.wants.get(&chat_id.0).cloned().unwrap_or_default();
+    if wants.is_empty() {
+        bot.send_message(chat_id, "ℹ️ La lista de deseos está vacía. Usa /want <título [año]|id IMDb>.").await?;
+        return Ok(());
+    }
+    let mut text = String::from("👀 Lista de deseos:");
+    for want in &wants {
+        text.push_str(&format!("\n#{} {} (desde {})", want.id, want.query, format_timestamp(want.added_at)));
+    }
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Command `/unwant <id>`: removes an entry from the chat's watchlist.
+async fn unwant_command(bot: Bot, msg: Message, id: String) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let Ok(id) = id.trim_start_matches('#').parse::<u64>() else {
+        bot.send_message(chat_id, "⚠️ Uso: /unwant <id>. Consulta los ids con /wants.").await?;
+        return Ok(());
+    };
+    let removed = {
+        let mut state = WATCHLIST.lock().await;
+        let removed = state.wants.get_mut(&chat_id.0).and_then(|wants| {
+            let pos = wants.iter().position(|w| w.id == id)?;
+            Some(wants.remove(pos))
+        });
+        if removed.is_some() {
+            state.wants.retain(|_, wants| !wants.is_empty());
+            save_json_state(WATCHLIST_FILE, &*state);
+        }
+        removed
+    };
+    if removed.is_some() {
+        stop_yts_monitor_if_idle().await;
+    }
+    let reply = match removed {
+        Some(want) => format!("🗑️ Eliminado de la lista de deseos: {}", want.query),
+        None => format!("ℹ️ No hay ningún deseo #{} en este chat.", id),
+    };
+    bot.send_message(chat_id, reply).await?;
+    Ok(())
+}
+
+
+// --- Existing Functions (modified slightly for safety or clarity if needed) ---
+fn parse_pattern(input: &str) -> Option<ScanPattern> { // Existing
+    let parts: Vec<&str> = input.split('-').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+    let parse_part = |s: &str| -> Option<(char, String)> {
+        let start_idx = s.find('[')?;
+        let end_idx = s.find(']')?;
+        if start_idx >= end_idx { return None; } // Ensure valid indices
+        let variable_char = s.get(start_idx + 1..end_idx)?.chars().next()?;
+        let prefix = s.get(..start_idx)?.to_string();
+        let suffix = s.get(end_idx + 1..)?.to_string();
+        Some((variable_char, format!("{}{{}}{}", prefix, suffix)))
+    };
+    let (start_char, start_tmpl) = parse_part(parts[0])?;
+    let (end_char, end_tmpl) = parse_part(parts[1])?;
+    if start_tmpl != end_tmpl { // This check might be too restrictive if templates are meant to differ
+        return None;
+    }
+    Some(ScanPattern {
+        start_char,
+        end_char,
+        template: start_tmpl,
+    })
+}
+
+/// Everything needed to run a scan; also the shape of a saved pattern preset.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ScanRequest {
+    pattern: String,
+    #[serde(default)]
+    site: Option<String>,
+    /// Hits whose title contains any of these words (case-insensitive) are dropped.
+    #[serde(default)]
+    exclude: Vec<String>,
+    /// Minimum seconds between two values of this scan, on top of the host's adaptive pacing.
+    #[serde(default)]
+    delay_seconds: Option<u64>,
+}
+
+impl ScanRequest {
+    /// Parses `<pattern> [site] [excluir=a,b] [delay=N]`.
+    fn parse(args: &[&str]) -> Result<ScanRequest, String> {
+        let (pattern, rest) = args.split_first().ok_or("Falta el patrón.")?;
+        let mut scan = ScanRequest { pattern: pattern.to_string(), site: None, exclude: Vec::new(), delay_seconds: None };
+        for arg in rest {
+            match arg.split_once('=') {
+                Some(("excluir", words)) => {
+                    scan.exclude = words.split(',').map(str::trim).filter(|w| !w.is_empty()).map(ToOwned::to_owned).collect();
+                }
+                Some(("delay", secs)) => {
+                    scan.delay_seconds = Some(secs.parse().map_err(|_| format!("delay inválido: {}", secs))?);
+                }
+                Some((key, _)) => return Err(format!("Opción desconocida: {}", key)),
+                None if scan.site.is_none() => scan.site = Some(arg.to_string()),
+                None => return Err(format!("Argumento inesperado: {}", arg)),
+            }
+        }
+        Ok(scan)
+    }
+
+    /// Checks the pattern and site up front so queued jobs don't fail later.
+    fn validate(&self) -> Result<(), String> {
+        if parse_pattern(&self.pattern).is_none() {
+            return Err("Formato de patrón inválido. Ejemplos: l[c]a-l[m]a, [A]bc-[Z]bc".to_string());
+        }
+        if find_site_profile(self.site.as_deref()).is_none() {
+            let available = SITE_PROFILES.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", ");
+            return Err(format!("Perfil de sitio desconocido. Disponibles: {}", available));
+        }
+        Ok(())
+    }
+
+    fn is_excluded(&self, title: &str) -> bool {
+        let title = title.to_lowercase();
+        self.exclude.iter().any(|word| title.contains(&word.to_lowercase()))
+    }
+
+    fn describe(&self) -> String {
+        let mut text = self.pattern.clone();
+        if let Some(site) = &self.site {
+            text.push_str(&format!(" {}", site));
+        }
+        if !self.exclude.is_empty() {
+            text.push_str(&format!(" excluir={}", self.exclude.join(",")));
+        }
+        if let Some(delay) = self.delay_seconds {
+            text.push_str(&format!(" delay={}", delay));
+        }
+        text
+    }
+}
+
+async fn check_links(bot: Bot, chat_id: ChatId, scan: ScanRequest) -> ResponseResult<()> { // Existing
+    let pattern = &scan.pattern;
+    let scan_pattern = match parse_pattern(pattern) {
+        Some(p) => p,
+        None => {
+            bot.send_message(chat_id, "⚠️ Formato de patrón inválido. Ejemplos:\n/check l[c]a-l[m]a\n/check [A]bc-[Z]bc")
+                .await?;
+            return Ok(());
+        }
+    };
+    let profile = match find_site_profile(scan.site.as_deref()) {
+        Some(p) => p,
+        None => {
+            let available = SITE_PROFILES.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", ");
+            bot.send_message(chat_id, format!("⚠️ Perfil de sitio desconocido. Disponibles: {}", available)).await?;
+            return Ok(());
+        }
+    };
+
+    let scan_header = format!("🔍 Escaneando con patrón: {} (sitio: {})...", pattern, profile.name);
+    let progress_msg = bot.send_message(chat_id, &scan_header).await?;
+    let start_byte = scan_pattern.start_char as u8;
+    let end_byte = scan_pattern.end_char as u8;
+    let (eff_start, eff_end) = if start_byte <= end_byte { (start_byte, end_byte) } else { (end_byte, start_byte) };
+    let total = scan_pattern.value_count();
+    let mut last_progress_update = tokio::time::Instant::now();
+
+    let mut found_hits: Vec<(String, PageHit)> = Vec::new();
+    let mut previous_value_start: Option<tokio::time::Instant> = None;
+    for (done, c_byte) in (eff_start..=eff_end).enumerate() {
+        if let (Some(delay), Some(previous)) = (scan.delay_seconds, previous_value_start) {
+            tokio::time::sleep_until(previous + Duration::from_secs(delay)).await;
+        }
+        previous_value_start = Some(tokio::time::Instant::now());
+        if done > 0 && last_progress_update.elapsed() >= Duration::from_secs(SCAN_PROGRESS_INTERVAL_SECONDS) {
+            let progress = format!(
+                "{}\nProgreso: {}/{} · Hallazgos: {} · Ritmo: {:.2} req/s",
+                scan_header, done, total, found_hits.len(), host_rate(&profile.host()).await
+            );
+            if let Err(e) = bot.edit_message_text(chat_id, progress_msg.id, progress).await {
+                debug!("No se pudo actualizar el progreso del escaneo: {:?}", e);
+            }
+            last_progress_update = tokio::time::Instant::now();
+        }
+        let current_char = c_byte as char;
+        // Ensure char is valid before forming URL, especially if range is large
+        if !current_char.is_alphanumeric() && !"[](){}".contains(current_char) { // Example filter, adjust as needed
+             // debug!("Skipping non-alphanumeric char in pattern scan: {}", current_char);
+             // continue;
+        }
+
+        let param_val = scan_pattern.template.replace("{}", &current_char.to_string());
+        let url = profile.describe_target(&param_val);
+        
+        info!("Verificando URL (scraper): {}", url);
+        
+        match check_page(&param_val, profile).await {
+            Ok(Some(mut hit)) => {
+                if scan.is_excluded(&hit.title) {
+                    debug!("Hallazgo excluido por filtro en {}: '{}'", url, hit.title);
+                } else if !profile.is_unwanted_title(&hit.title) {
+                    hit.expanded_links = expand_links(&hit.links).await;
+                    hit.link_status = check_links_liveness(&hit.links, &hit.expanded_links).await;
+                    let mut message_text = format_hit_message(&url, &hit);
+                    if let Some(archive_id) = archive_hit(chat_id, profile, &param_val, &url, &hit).await {
+                        message_text.push_str(&format!("\n🗄️ Copia: /archive {}", archive_id));
+                    }
+                    send_hit_notification(&bot, chat_id, message_text, &hit).await?;
+                    found_hits.push((url, hit));
+                } else {
+                    debug!("Título no deseado o vacío encontrado en {}: '{}'", url, hit.title);
+                }
+            }
+            Ok(None) => {
+                debug!("No se encontró título en {}", url);
+                continue;
+            }
+            Err(ScanError::SessionExpired(reason)) => {
+                error!("Sesión expirada en el perfil {}: {}", profile.name, reason);
+                bot.send_message(chat_id, format!("🔒 La sesión en {} expiró y no se pudo renovar ({}). Escaneo detenido.", profile.name, reason)).await?;
+                return Ok(());
+            }
+            Err(e) => {
+                error!("Error al verificar la página {} (scraper): {}", url, e);
+                // Optionally notify user about specific page error, or just log
+                // bot.send_message(chat_id, format!("⚠️ Error al verificar {}", url)).await?;
+                continue;
+            }
+        }
+    }
+    bot.send_message(chat_id, "🚀 Escaneo (scraper) completado!").await?;
+    if !found_hits.is_empty() {
+        bot.send_message(chat_id, format_scan_report(&found_hits)).await?;
+    }
+    Ok(())
+}
+
+/// A page that produced a title, together with where the request ended up.
+struct PageHit {
+    title: String,
+    /// Named values extracted from the body (JSONPath fields in `json` mode).
+    fields: Vec<(String, String)>,
+    /// Named regex capture groups found in the page text.
+    captures: Vec<(String, String)>,
+    final_url: Url,
+    /// Requested URL followed by every redirect target, only populated when redirected.
+    redirect_chain: Vec<Url>,
+    /// Absolute http(s) links found in the page (anchors and plain-text URLs).
+    links: Vec<String>,
+    /// Liveness of each entry in `links`, filled in before the hit is sent.
+    link_status: HashMap<String, LinkStatus>,
+    /// Final destination of each link after following shorteners/redirectors.
+    expanded_links: HashMap<String, ExpandedLink>,
+    /// OpenGraph and JSON-LD data (HTML mode only).
+    metadata: PageMetadata,
+    /// Raw response body, kept for archiving.
+    body: String,
+}
+
+/// Structured metadata published by most content pages.
+#[derive(Debug, Default, Clone)]
+struct PageMetadata {
+    og_title: Option<String>,
+    og_description: Option<String>,
+    /// Absolute URL of `og:image`.
+    og_image: Option<String>,
+    og_type: Option<String>,
+    /// Every JSON-LD item found, with arrays and `@graph` containers flattened.
+    json_ld: Vec<serde_json::Value>,
+}
+
+impl PageMetadata {
+    /// One line per JSON-LD item: its `@type` and `name`/`headline`.
+    fn json_ld_summary(&self) -> Vec<String> {
+        self.json_ld
+            .iter()
+            .filter_map(|item| {
+                let kind = item.get("@type").map(|t| match t {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                })?;
+                let name = item.get("name").or_else(|| item.get("headline")).and_then(|n| n.as_str());
+                Some(match name {
+                    Some(name) => format!("{}: {}", kind, name),
+                    None => kind,
+                })
+            })
+            .collect()
+    }
+}
+
+fn extract_metadata(document: &Html, base: &Url) -> PageMetadata {
+    let meta_selector = Selector::parse("meta[property], meta[name]").unwrap();
+    let mut metadata = PageMetadata::default();
+    for meta in document.select(&meta_selector) {
+        let element = meta.value();
+        let key = element.attr("property").or_else(|| element.attr("name")).unwrap_or_default();
+        let Some(content) = element.attr("content").map(str::trim).filter(|c| !c.is_empty()) else { continue };
+        let slot = match key {
+            "og:title" => &mut metadata.og_title,
+            "og:description" => &mut metadata.og_description,
+            "og:image" | "og:image:url" | "og:image:secure_url" => &mut metadata.og_image,
+            "og:type" => &mut metadata.og_type,
+            _ => continue,
+        };
+        if slot.is_none() {
+            *slot = Some(content.to_string());
+        }
+    }
+    metadata.og_image = metadata.og_image.and_then(|image| base.join(&image).ok()).map(|u| u.to_string());
+
+    let ld_selector = Selector::parse(r#"script[type="application/ld+json"]"#).unwrap();
+    for script in document.select(&ld_selector) {
+        let raw = script.text().collect::<String>();
+        match serde_json::from_str::<serde_json::Value>(raw.trim()) {
+            Ok(value) => flatten_json_ld(value, &mut metadata.json_ld),
+            Err(e) => debug!("Bloque JSON-LD inválido en {}: {}", base, e),
+        }
+    }
+    metadata
+}
+
+fn flatten_json_ld(value: serde_json::Value, out: &mut Vec<serde_json::Value>) {
+    match value {
+        serde_json::Value::Array(items) => items.into_iter().for_each(|item| flatten_json_ld(item, out)),
+        serde_json::Value::Object(mut map) => match map.remove("@graph") {
+            Some(graph) => flatten_json_ld(graph, out),
+            None => out.push(serde_json::Value::Object(map)),
+        },
+        _ => {}
+    }
+}
+
+/// Sends a hit as a photo with caption when the page has an `og:image`, otherwise as text.
+/// Mirrors `send_movie_notification`: any photo failure falls back to a plain message.
+async fn send_hit_notification(bot: &Bot, chat_id: ChatId, text: String, hit: &PageHit) -> ResponseResult<()> {
+    let text = truncate_message(text, TELEGRAM_MESSAGE_LIMIT);
+    let Some(img_url) = hit.metadata.og_image.as_deref().and_then(|image| Url::parse(image).ok()) else {
+        bot.send_message(chat_id, text).await?;
+        return Ok(());
+    };
+    let fits_caption = text.chars().count() <= TELEGRAM_CAPTION_LIMIT;
+    let caption = if fits_caption { text.clone() } else { truncate_message(format!("✅ {}", hit.title), TELEGRAM_CAPTION_LIMIT) };
+    match bot.send_photo(chat_id, InputFile::url(img_url)).caption(caption).await {
+        Ok(_) => {
+            if !fits_caption {
+                bot.send_message(chat_id, text).await?;
+            }
+        }
+        Err(e) => {
+            error!("Failed to send photo for hit '{}' to chat {}: {:?}. Trying text message.", hit.title, chat_id, e);
+            bot.send_message(chat_id, text).await?;
+        }
+    }
+    Ok(())
+}
+
+fn format_hit_message(url: &str, hit: &PageHit) -> String {
+    let mut text = format!("✅ ¡Encontrado!\nURL: {}\nTítulo: {}", url, hit.title);
+    for (name, value) in &hit.fields {
+        text.push_str(&format!("\n{}: {}", name, value));
+    }
+    for (name, value) in hit.captures.iter().take(HIT_MESSAGE_MAX_CAPTURES) {
+        text.push_str(&format!("\n🔑 {}: {}", name, value));
+    }
+    if hit.captures.len() > HIT_MESSAGE_MAX_CAPTURES {
+        text.push_str(&format!("\n… y {} capturas más", hit.captures.len() - HIT_MESSAGE_MAX_CAPTURES));
+    }
+    if let Some(og_title) = hit.metadata.og_title.as_ref().filter(|t| **t != hit.title) {
+        text.push_str(&format!("\n🏷️ {}", og_title));
+    }
+    if let Some(description) = &hit.metadata.og_description {
+        let short = description.chars().take(HIT_DESCRIPTION_MAX_CHARS).collect::<String>();
+        let ellipsis = if short.len() < description.len() { "…" } else { "" };
+        text.push_str(&format!("\n📝 {}{}", short, ellipsis));
+    }
+    for item in hit.metadata.json_ld_summary().into_iter().take(HIT_MESSAGE_MAX_JSON_LD) {
+        text.push_str(&format!("\n📦 {}", item));
+    }
+    if !hit.redirect_chain.is_empty() {
+        let chain = hit.redirect_chain.iter().map(Url::as_str).collect::<Vec<_>>().join(" → ");
+        text.push_str(&format!("\nURL final: {}\nRedirecciones: {}", hit.final_url, chain));
+    }
+    if !hit.links.is_empty() {
+        text.push_str(&format!("\nEnlaces ({}):", hit.links.len()));
+        for link in hit.links.iter().take(HIT_MESSAGE_MAX_LINKS) {
+            text.push_str(&format!("\n{}", format_link_line(link, hit.link_status.get(link), hit.expanded_links.get(link))));
+        }
+        if hit.links.len() > HIT_MESSAGE_MAX_LINKS {
+            text.push_str(&format!("\n… y {} más", hit.links.len() - HIT_MESSAGE_MAX_LINKS));
+        }
+    }
+    truncate_message(text, TELEGRAM_MESSAGE_LIMIT - HIT_MESSAGE_RESERVED_CHARS)
+}
+
+/// Cuts `text` to at most `limit` characters, ending in "…" when something was dropped.
+fn truncate_message(text: String, limit: usize) -> String {
+    if text.chars().count() > limit {
+        text.chars().take(limit - 1).collect::<String>() + "…"
+    } else {
+        text
+    }
+}
+
+/// End-of-scan summary listing every hit with its captured values.
+fn format_scan_report(hits: &[(String, PageHit)]) -> String {
+    let mut report = format!("📋 Resumen del escaneo: {} hallazgo(s)", hits.len());
+    for (url, hit) in hits {
+        report.push_str(&format!("\n\n• {}\n  {}", url, hit.title));
+        for (name, value) in &hit.captures {
+            report.push_str(&format!("\n  🔑 {}: {}", name, value));
+        }
+        if !hit.links.is_empty() {
+            report.push_str(&format!("\n  🔗 {}", format_liveness_counts(&hit.link_status)));
+        }
+    }
+    if report.chars().count() > TELEGRAM_MESSAGE_LIMIT {
+        report = report.chars().take(TELEGRAM_MESSAGE_LIMIT - 1).collect::<String>() + "…";
+    }
+    report
+}
+
+/// Response obtained after applying a site's redirect policy.
+struct FollowedResponse {
+    response: reqwest::Response,
+    /// Requested URL followed by every redirect target that was fetched.
+    chain: Vec<Url>,
+}
+
+impl FollowedResponse {
+    fn final_url(&self) -> &Url {
+        self.chain.last().expect("chain always holds the requested URL")
+    }
+
+    fn redirected(&self) -> bool {
+        self.chain.len() > 1
+    }
+}
+
+fn redirect_location(res: &reqwest::Response) -> Option<Url> {
+    let location = res.headers().get(reqwest::header::LOCATION)?.to_str().ok()?;
+    res.url().join(location).ok()
+}
+
+/// Sends `request` and follows redirects by hand according to `policy`, recording every hop.
+/// Stops early (returning the 3xx response) when the policy forbids the next hop.
+/// With a `session` profile, its headers and cookies are sent and updated on every hop.
+async fn send_following_redirects(
+    request: reqwest::Request,
+    policy: &RedirectPolicy,
+    session: Option<&SiteProfile>,
+) -> Result<FollowedResponse, reqwest::Error> {
+    let mut chain = vec![request.url().clone()];
+    let mut request = request;
+    // Session headers and cookies only belong to the site itself, never to hosts it redirects to
+    let session_hosts = session.map(|profile| [profile.host(), chain[0].host_str().unwrap_or_default().to_string()]);
+    loop {
+        let session_here = session.zip(session_hosts.as_ref()).filter(|(_, hosts)| {
+            request.url().host_str().is_some_and(|host| hosts.iter().any(|h| h == host))
+        });
+        if let Some((profile, _)) = session_here {
+            apply_session(profile, &mut request).await;
+        }
+        let retry_template = request.try_clone();
+        let method = request.method().clone();
+        let headers = request.headers().clone();
+        let res = execute_paced(request).await?;
+        if let Some((profile, _)) = session_here {
+            store_session_cookies(profile, &res).await;
+        }
+        let status = res.status();
+        if !status.is_redirection() || !policy.follow {
+            return Ok(FollowedResponse { response: res, chain });
+        }
+        let Some(location) = redirect_location(&res) else {
+            return Ok(FollowedResponse { response: res, chain });
+        };
+        if chain.len() > policy.max_hops {
+            debug!("Límite de {} redirecciones alcanzado en {}", policy.max_hops, chain[0]);
+            return Ok(FollowedResponse { response: res, chain });
+        }
+        if chain.contains(&location) {
+            debug!("Bucle de redirecciones detectado hacia {}", location);
+            return Ok(FollowedResponse { response: res, chain });
+        }
+        if policy.same_host_only && location.host_str() != chain[0].host_str() {
+            debug!("Redirección a otro host no permitida: {}", location);
+            return Ok(FollowedResponse { response: res, chain });
+        }
+        let cross_host = location.host_str() != chain.last().and_then(|u| u.host_str());
+        chain.push(location.clone());
+
+        // 307/308 must repeat the same method and body; everything else becomes a GET (HEAD stays HEAD)
+        request = match (status.as_u16(), retry_template) {
+            (307 | 308, Some(mut same)) => {
+                *same.url_mut() = location;
+                same
+            }
+            _ => {
+                let next_method = if method == reqwest::Method::HEAD { reqwest::Method::HEAD } else { reqwest::Method::GET };
+                let mut next = reqwest::Request::new(next_method, location);
+                *next.headers_mut() = headers;
+                next.headers_mut().remove(reqwest::header::CONTENT_TYPE);
+                next.headers_mut().remove(reqwest::header::CONTENT_LENGTH);
+                next
+            }
+        };
+        // Like browsers, never hand credentials to a different host
+        if cross_host {
+            for header in [reqwest::header::AUTHORIZATION, reqwest::header::COOKIE, reqwest::header::PROXY_AUTHORIZATION] {
+                request.headers_mut().remove(header);
+            }
+            // Custom session headers (API keys and the like) are credentials too
+            for name in session.and_then(|profile| profile.session.as_ref()).into_iter().flat_map(|s| s.headers.keys()) {
+                request.headers_mut().remove(name.as_str());
+            }
+        }
+    }
+}
+
+/// Text nodes of the page outside `<script>`/`<style>`, one per line, so captures and links
+/// cannot run across element boundaries or into inline code.
+fn visible_text(document: &Html) -> String {
+    document
+        .root_element()
+        .descendants()
+        .filter_map(|node| node.value().as_text().map(|text| (node, text)))
+        .filter(|(node, _)| {
+            !node.ancestors().any(|a| a.value().as_element().is_some_and(|e| matches!(e.name(), "script" | "style" | "noscript")))
+        })
+        .map(|(_, text)| text.trim())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Classifies a followed response as a miss based on where its redirects ended up.
+fn redirect_miss_reason(followed: &FollowedResponse, profile: &SiteProfile) -> Option<String> {
+    let policy = &profile.redirects;
+    if followed.response.status().is_redirection() {
+        let target = redirect_location(&followed.response).map(|u| u.to_string()).unwrap_or_default();
+        return Some(format!("redirección no seguida ({}) hacia {}", followed.response.status(), target));
+    }
+    if !followed.redirected() {
+        return None;
+    }
+    let final_url = followed.final_url();
+    if policy.home_is_miss && profile.home_url().is_some_and(|home| &home == final_url) {
+        return Some(format!("redirigido a la página principal ({})", final_url));
+    }
+    if let Some(prefix) = policy.miss_urls.iter().find(|prefix| final_url.as_str().starts_with(prefix.as_str())) {
+        return Some(format!("redirigido a {} (coincide con {})", final_url, prefix));
+    }
+    None
+}
+
+/// Result of a cheap pre-check done before downloading a page body.
+enum ProbeVerdict {
+    /// The page is an obvious miss; the reason is only logged.
+    Miss(String),
+    /// The page may be a hit. Carries the full body and redirect chain if the probe already downloaded it.
+    Candidate(Option<(String, Vec<Url>)>),
+}
+
+/// Reads the raw Content-Length header (`Response::content_length` reports 0 for HEAD bodies).
+fn header_content_length(res: &reqwest::Response) -> Option<u64> {
+    res.headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+/// Total size from a `Content-Range: bytes 0-4095/12345` header.
+fn content_range_total(res: &reqwest::Response) -> Option<u64> {
+    res.headers()
+        .get(reqwest::header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit('/').next())
+        .and_then(|total| total.parse().ok())
+}
+
+fn extract_title(html_content: &str) -> Option<String> {
+    document_title(&Html::parse_document(html_content))
+}
+
+fn document_title(document: &Html) -> Option<String> {
+    let title_selector = Selector::parse("title").unwrap(); // .unwrap() is okay if "title" is always valid selector
+    document
+        .select(&title_selector)
+        .next()
+        .map(|title_element| title_element.text().collect::<String>().trim().to_string())
+        .filter(|t| !t.is_empty()) // Ensure title is not just whitespace
+}
+
+/// Runs the profile's regexes over `text`. Groups without a name are ignored unless the
+/// regex has no named groups at all, in which case the whole match is reported.
+fn extract_regex_captures(text: &str, profile: &SiteProfile) -> Vec<(String, String)> {
+    let mut captures: Vec<(String, String)> = Vec::new();
+    for regex in &profile.extract_regex {
+        let names = regex.capture_names().flatten().collect::<Vec<_>>();
+        for caps in regex.captures_iter(text) {
+            let found = if names.is_empty() {
+                vec![("Coincidencia".to_string(), caps[0].trim().to_string())]
+            } else {
+                names
+                    .iter()
+                    .filter_map(|name| caps.name(name).map(|m| (name.to_string(), m.as_str().trim().to_string())))
+                    .collect()
+            };
+            for pair in found {
+                if !pair.1.is_empty() && !captures.contains(&pair) {
+                    captures.push(pair);
+                }
+            }
+        }
+    }
+    captures
+}
+
+static PLAIN_URL_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"https?://[^\s<>"'`]+"#).unwrap());
+
+/// Collects absolute http(s) links from anchors (resolved against `base`) and plain-text URLs.
+fn extract_links(document: Option<&Html>, text: &str, base: &Url) -> Vec<String> {
+    let mut links: Vec<String> = Vec::new();
+    let mut push = |link: String| {
+        if !links.contains(&link) {
+            links.push(link);
+        }
+    };
+    if let Some(document) = document {
+        let anchor_selector = Selector::parse("a[href]").unwrap();
+        for anchor in document.select(&anchor_selector) {
+            if let Some(url) = anchor.value().attr("href").and_then(|href| base.join(href).ok()) {
+                if matches!(url.scheme(), "http" | "https") && url.host_str() != base.host_str() {
+                    push(url.to_string());
+                }
+            }
+        }
+    }
+    for m in PLAIN_URL_RE.find_iter(text) {
+        push(m.as_str().trim_end_matches(['.', ',', ')', ';']).to_string());
+    }
+    links
+}
+
+/// Renders every node matched by a JSONPath expression; strings are shown without quotes.
+fn query_json_path(value: &serde_json::Value, path: &str) -> Option<String> {
+    let json_path = match serde_json_path::JsonPath::parse(path) {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Expresión JSONPath inválida '{}': {}", path, e);
+            return None;
+        }
+    };
+    let rendered = json_path
+        .query(value)
+        .all()
+        .into_iter()
+        .map(|node| match node {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+        .collect::<Vec<_>>();
+    if rendered.is_empty() { None } else { Some(rendered.join(", ")) }
+}
+
+/// JSON-mode counterpart of `extract_title`: returns the title plus the configured fields.
+fn extract_json(body: &str, profile: &SiteProfile) -> Option<(String, Vec<(String, String)>)> {
+    let value: serde_json::Value = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("Respuesta no es JSON válido para el perfil {}: {}", profile.name, e);
+            return None;
+        }
+    };
+    let title = query_json_path(&value, &profile.json_title).filter(|t| !t.trim().is_empty())?;
+    let fields = profile
+        .json_fields
+        .iter()
+        .filter_map(|field| query_json_path(&value, &field.path).map(|v| (field.name.clone(), v)))
+        .collect();
+    Some((title, fields))
+}
+
+/// Runs the profile's probe strategy. `Get` never calls this.
+async fn probe_page(value: &str, profile: &SiteProfile) -> Result<ProbeVerdict, ScanError> {
+    if profile.request_method() != reqwest::Method::GET {
+        // HEAD/Range only make sense for plain GET targets; POST lookups always need the full request
+        return Ok(ProbeVerdict::Candidate(None));
+    }
+    let mut request = profile.build_request(value)?;
+    match profile.probe {
+        ProbeStrategy::Get => return Ok(ProbeVerdict::Candidate(None)),
+        ProbeStrategy::Head => *request.method_mut() = reqwest::Method::HEAD,
+        ProbeStrategy::RangeGet => {
+            let range = format!("bytes=0-{}", profile.probe_range_bytes.saturating_sub(1));
+            let range = reqwest::header::HeaderValue::from_str(&range).expect("range header is always ASCII");
+            request.headers_mut().insert(reqwest::header::RANGE, range);
+        }
+    }
+    let followed = send_following_redirects(request, &profile.redirects, Some(profile)).await?;
+    if let Some(reason) = session_expired_reason(profile, followed.final_url(), None) {
+        return Err(ScanError::SessionExpired(reason));
+    }
+
+    let status = followed.response.status();
+    if status == reqwest::StatusCode::METHOD_NOT_ALLOWED || status == reqwest::StatusCode::NOT_IMPLEMENTED {
+        // Server doesn't support HEAD; fall back to a normal GET
+        return Ok(ProbeVerdict::Candidate(None));
+    }
+    if let Some(reason) = redirect_miss_reason(&followed, profile) {
+        return Ok(ProbeVerdict::Miss(reason));
+    }
+    if !status.is_success() {
+        return Ok(ProbeVerdict::Miss(format!("HTTP {}", status)));
+    }
+
+    let total_length = if status == reqwest::StatusCode::PARTIAL_CONTENT {
+        content_range_total(&followed.response)
+    } else {
+        header_content_length(&followed.response)
+    };
+    if let (Some(min), Some(len)) = (profile.min_content_length, total_length) {
+        if len < min {
+            return Ok(ProbeVerdict::Miss(format!("Content-Length {} < {}", len, min)));
+        }
+    }
+
+    if profile.probe == ProbeStrategy::Head {
+        return Ok(ProbeVerdict::Candidate(None));
+    }
+
+    // Range GET: a 200 means the server ignored the range and sent the whole page
+    let FollowedResponse { response, chain } = followed;
+    if status == reqwest::StatusCode::OK {
+        return Ok(ProbeVerdict::Candidate(Some((response.text().await?, chain))));
+    }
+    let prefix = response.bytes().await?;
+    let prefix = String::from_utf8_lossy(&prefix);
+    // Only trust the title if the closing tag made it into the prefix
+    if prefix.to_ascii_lowercase().contains("</title>") {
+        if let Some(title) = extract_title(&prefix) {
+            if profile.is_unwanted_title(&title) {
+                return Ok(ProbeVerdict::Miss(format!("título no deseado en el prefijo: '{}'", title)));
+            }
+        }
+    }
+    Ok(ProbeVerdict::Candidate(None))
+}
+
+async fn check_page(value: &str, profile: &SiteProfile) -> Result<Option<PageHit>, ScanError> { // Existing
+    ensure_session(profile).await?;
+    match fetch_page(value, profile).await {
+        Err(ScanError::SessionExpired(reason)) if profile.session.as_ref().is_some_and(|s| s.login.is_some()) => {
+            info!("Sesión del perfil {} expirada ({}); iniciando sesión de nuevo.", profile.name, reason);
+            clear_session_cookies(profile).await;
+            login_site(profile).await?;
+            fetch_page(value, profile).await
+        }
+        other => other,
+    }
+}
+
+/// Probes and downloads a single value's page and extracts the hit, without session recovery.
+async fn fetch_page(value: &str, profile: &SiteProfile) -> Result<Option<PageHit>, ScanError> {
+    let url = profile.target_url(value);
+    let prefetched = match probe_page(value, profile).await? {
+        ProbeVerdict::Miss(reason) => {
+            debug!("Sondeo descartó {}: {}", url, reason);
+            return Ok(None);
+        }
+        ProbeVerdict::Candidate(body) => body,
+    };
+
+    let (html_content, chain) = match prefetched {
+        Some(fetched) => fetched,
+        None => {
+            let followed = send_following_redirects(profile.build_request(value)?, &profile.redirects, Some(profile)).await?;
+            if let Some(reason) = session_expired_reason(profile, followed.final_url(), None) {
+                return Err(ScanError::SessionExpired(reason));
+            }
+            if let Some(reason) = redirect_miss_reason(&followed, profile) {
+                debug!("Descartado {}: {}", url, reason);
+                return Ok(None);
+            }
+            if !followed.response.status().is_success() {
+                error!("HTTP error {} for URL: {}", followed.response.status(), url);
+                return Ok(None); // Or return specific error
+            }
+            let FollowedResponse { response, chain } = followed;
+            (response.text().await?, chain)
+        }
+    };
+    let final_url = chain.last().cloned().expect("chain always holds the requested URL");
+    if let Some(reason) = session_expired_reason(profile, &final_url, Some(&html_content)) {
+        return Err(ScanError::SessionExpired(reason));
+    }
+    let redirect_chain = if chain.len() > 1 { chain } else { Vec::new() };
+    let (extracted, page_text, links, metadata) = match profile.mode {
+        ScanMode::Html => {
+            let document = Html::parse_document(&html_content);
+            let text = visible_text(&document);
+            let links = extract_links(Some(&document), &text, &final_url);
+            let metadata = extract_metadata(&document, &final_url);
+            (document_title(&document).map(|title| (title, Vec::new())), text, links, metadata)
+        }
+        ScanMode::Json => {
+            let links = extract_links(None, &html_content, &final_url);
+            (extract_json(&html_content, profile), html_content.clone(), links, PageMetadata::default())
+        }
+    };
+    Ok(extracted.map(|(title, fields)| PageHit {
+        title,
+        fields,
+        captures: extract_regex_captures(&page_text, profile),
+        final_url,
+        redirect_chain,
+        links,
+        link_status: HashMap::new(),
+        expanded_links: HashMap::new(),
+        metadata,
+        body: html_content,
+    }))
+}
+
+// --- Scan Queue ---
+// Every scan goes through one queue: admins' jobs first, then round-robin across owning chats,
+// with at most SCAN_MAX_CONCURRENT_JOBS scans running at once.
+const ADMIN_IDS_ENV: &str = "BOT_ADMIN_IDS";
+const SCAN_MAX_CONCURRENT_ENV: &str = "SCAN_MAX_CONCURRENT_JOBS";
+const DEFAULT_SCAN_MAX_CONCURRENT: usize = 2;
+
+// Telegram user ids allowed to jump the queue (comma-separated in BOT_ADMIN_IDS)
+static ADMIN_IDS: Lazy<Vec<u64>> = Lazy::new(|| {
+    std::env::var(ADMIN_IDS_ENV)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .collect()
+});
+
+static SCAN_MAX_CONCURRENT: Lazy<usize> = Lazy::new(|| {
+    std::env::var(SCAN_MAX_CONCURRENT_ENV)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_SCAN_MAX_CONCURRENT)
+});
+
+fn is_admin(user: Option<&teloxide::types::User>) -> bool {
+    user.is_some_and(|u| ADMIN_IDS.contains(&u.id.0))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ScanPriority {
+    Normal,
+    High,
+}
+
+#[derive(Debug, Clone)]
+struct ScanJob {
+    id: u64,
+    owner: ChatId,
+    requested_by: Option<UserId>,
+    priority: ScanPriority,
+    scan: ScanRequest,
+    enqueued_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Default)]
+struct ScanQueue {
+    next_id: u64,
+    pending: Vec<ScanJob>,
+    running: Vec<(ScanJob, chrono::DateTime<chrono::Utc>)>,
+    /// Owners in round-robin order; the front is served next.
+    rotation: std::collections::VecDeque<ChatId>,
+}
+
+impl ScanQueue {
+    /// Removes and returns the next job: highest priority first, then the first owner in rotation
+    /// that has a job of that priority. The served owner moves to the back of the rotation.
+    fn pop_next(&mut self) -> Option<ScanJob> {
+        let priority = self.pending.iter().map(|job| job.priority).max()?;
+        let (rotation_pos, job_pos) = self.rotation.iter().enumerate().find_map(|(rotation_pos, owner)| {
+            self.pending
+                .iter()
+                .position(|job| job.owner == *owner && job.priority == priority)
+                .map(|job_pos| (rotation_pos, job_pos))
+        })?;
+        let owner = self.rotation.remove(rotation_pos)?;
+        self.rotation.push_back(owner);
+        Some(self.pending.remove(job_pos))
+    }
+
+    /// Job ids in the order they would start if nothing else were enqueued.
+    fn planned_order(&self) -> Vec<u64> {
+        let mut simulated = ScanQueue {
+            next_id: self.next_id,
+            pending: self.pending.clone(),
+            running: Vec::new(),
+            rotation: self.rotation.clone(),
+        };
+        std::iter::from_fn(|| simulated.pop_next().map(|job| job.id)).collect()
+    }
+
+    /// 1-based position of a pending job.
+    fn position_of(&self, job_id: u64) -> Option<usize> {
+        self.planned_order().iter().position(|id| *id == job_id).map(|pos| pos + 1)
+    }
+}
+
+static SCAN_QUEUE: Lazy<Arc<Mutex<ScanQueue>>> = Lazy::new(|| Arc::new(Mutex::new(ScanQueue::default())));
+// Wakes the queue runner when a job is enqueued or finishes
+static SCAN_QUEUE_NOTIFY: Lazy<tokio::sync::Notify> = Lazy::new(tokio::sync::Notify::new);
+
+/// Adds a scan to the queue and returns its job id and position.
+async fn enqueue_scan(owner: ChatId, requested_by: Option<UserId>, priority: ScanPriority, scan: ScanRequest) -> (u64, usize) {
+    let (id, position) = {
+        let mut queue = SCAN_QUEUE.lock().await;
+        queue.next_id += 1;
+        let id = queue.next_id;
+        if !queue.rotation.contains(&owner) {
+            queue.rotation.push_back(owner);
+        }
+        queue.pending.push(ScanJob { id, owner, requested_by, priority, scan, enqueued_at: chrono::Utc::now() });
+        let position = queue.position_of(id).unwrap_or(queue.pending.len());
+        (id, position)
+    };
+    SCAN_QUEUE_NOTIFY.notify_one();
+    (id, position)
+}
+
+/// Validates a scan requested from a message, enqueues it and tells the user where it stands.
+async fn submit_scan(bot: Bot, msg: &Message, scan: ScanRequest) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    if let Err(e) = scan.validate() {
+        bot.send_message(chat_id, format!("⚠️ {}", e)).await?;
+        return Ok(());
+    }
+    let user = msg.from();
+    let priority = if is_admin(user) { ScanPriority::High } else { ScanPriority::Normal };
+    let (job_id, position) = match charge_quota_and_enqueue(user, chat_id, priority, scan, true).await {
+        Ok(queued) => queued,
+        Err(e) => {
+            bot.send_message(chat_id, e).await?;
+            return Ok(());
+        }
+    };
+    let running = SCAN_QUEUE.lock().await.running.len();
+    if position > 1 || running >= *SCAN_MAX_CONCURRENT {
+        bot.send_message(chat_id, format!("📥 Escaneo #{} en cola (posición {}). Consulta /jobs.", job_id, position)).await?;
+    }
+    Ok(())
+}
+
+/// Background loop that starts queued jobs whenever a slot is free.
+async fn run_scan_queue(bot: Bot) {
+    loop {
+        loop {
+            let job = {
+                let mut queue = SCAN_QUEUE.lock().await;
+                if queue.running.len() >= *SCAN_MAX_CONCURRENT {
+                    None
+                } else {
+                    let job = queue.pop_next();
+                    if let Some(job) = &job {
+                        queue.running.push((job.clone(), chrono::Utc::now()));
+                    }
+                    job
+                }
+            };
+            let Some(job) = job else { break };
+            info!("Iniciando escaneo #{} para chat {} ({:?})", job.id, job.owner, job.priority);
+            let bot_clone = bot.clone();
+            tokio::spawn(async move {
+                // The scan runs in its own task so a panic is caught by the JoinHandle and the slot is still released
+                match tokio::spawn(check_links(bot_clone, job.owner, job.scan)).await {
+                    Ok(Err(e)) => error!("Error en escaneo #{} para chat {}: {:?}", job.id, job.owner, e),
+                    Err(e) => error!("El escaneo #{} para chat {} terminó de forma anómala: {:?}", job.id, job.owner, e),
+                    Ok(Ok(())) => {}
+                }
+                let mut queue = SCAN_QUEUE.lock().await;
+                queue.running.retain(|(running, _)| running.id != job.id);
+                let owner_has_work = queue.pending.iter().any(|j| j.owner == job.owner)
+                    || queue.running.iter().any(|(j, _)| j.owner == job.owner);
+                if !owner_has_work {
+                    queue.rotation.retain(|owner| *owner != job.owner);
+                }
+                drop(queue);
+                SCAN_QUEUE_NOTIFY.notify_one();
+            });
+        }
+        SCAN_QUEUE_NOTIFY.notified().await;
+    }
+}
+
+/// Command `/jobs`: running scans and queue positions (admins see every chat's jobs).
+async fn jobs_command(bot: Bot, msg: Message) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let see_all = is_admin(msg.from());
+    let text = {
+        let queue = SCAN_QUEUE.lock().await;
+        let visible = |owner: ChatId| see_all || owner == chat_id;
+        let mut text = format!(
+            "🧵 Escaneos: {} en ejecución (máx. {}), {} en cola",
+            queue.running.len(), *SCAN_MAX_CONCURRENT, queue.pending.len()
+        );
+        for (job, started_at) in queue.running.iter().filter(|(job, _)| visible(job.owner)) {
+            let minutes = (chrono::Utc::now() - *started_at).num_minutes();
+            text.push_str(&format!("\n▶️ #{} · {} · hace {} min", job.id, job.scan.describe(), minutes));
+            if see_all {
+                text.push_str(&format!(" · chat {}", job.owner));
+            }
+        }
+        let order = queue.planned_order();
+        for (pos, job_id) in order.iter().enumerate() {
+            let Some(job) = queue.pending.iter().find(|job| job.id == *job_id) else { continue };
+            if !visible(job.owner) {
+                continue;
+            }
+            let priority = if job.priority == ScanPriority::High { " ⭐" } else { "" };
+            text.push_str(&format!("\n⏳ #{} · {} · posición {}/{}{}", job.id, job.scan.describe(), pos + 1, order.len(), priority));
+            if see_all {
+                let minutes = (chrono::Utc::now() - job.enqueued_at).num_minutes();
+                text.push_str(&format!(" · chat {} · esperando {} min", job.owner, minutes));
+                if let Some(user) = job.requested_by {
+                    text.push_str(&format!(" · usuario {}", user));
+                }
+            }
+        }
+        text
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+// --- Scan Quotas ---
+// Limits apply separately to each user and each chat; admins are exempt and can set overrides.
+const QUOTAS_FILE: &str = "quotas.json";
+const QUOTA_MAX_JOBS_ENV: &str = "QUOTA_MAX_CONCURRENT_JOBS";
+const QUOTA_MAX_URLS_ENV: &str = "QUOTA_MAX_URLS_PER_DAY";
+const QUOTA_COOLDOWN_ENV: &str = "QUOTA_COOLDOWN_SECONDS";
+const DEFAULT_QUOTA_MAX_JOBS: usize = 2;
+const DEFAULT_QUOTA_MAX_URLS_PER_DAY: u64 = 500;
+const DEFAULT_QUOTA_COOLDOWN_SECONDS: i64 = 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct QuotaLimits {
+    /// Queued plus running scans.
+    max_jobs: usize,
+    max_urls_per_day: u64,
+    cooldown_seconds: i64,
+}
+
+static DEFAULT_QUOTA_LIMITS: Lazy<QuotaLimits> = Lazy::new(|| {
+    let env_or = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<i64>().ok()).filter(|v| *v >= 0);
+    QuotaLimits {
+        max_jobs: env_or(QUOTA_MAX_JOBS_ENV).map_or(DEFAULT_QUOTA_MAX_JOBS, |v| v as usize),
+        max_urls_per_day: env_or(QUOTA_MAX_URLS_ENV).map_or(DEFAULT_QUOTA_MAX_URLS_PER_DAY, |v| v as u64),
+        cooldown_seconds: env_or(QUOTA_COOLDOWN_ENV).unwrap_or(DEFAULT_QUOTA_COOLDOWN_SECONDS),
+    }
+});
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct QuotaUsage {
+    /// UTC day (YYYY-MM-DD) `urls_today` belongs to.
+    day: String,
+    urls_today: u64,
+    last_scan_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct QuotaState {
+    /// Keyed by `user:<id>` / `chat:<id>`.
+    usage: HashMap<String, QuotaUsage>,
+    overrides: HashMap<String, QuotaLimits>,
+}
+
+static QUOTA_STATE: Lazy<Arc<Mutex<QuotaState>>> = Lazy::new(|| Arc::new(Mutex::new(load_json_state(QUOTAS_FILE))));
+
+fn user_quota_key(user_id: UserId) -> String {
+    format!("user:{}", user_id.0)
+}
+
+fn chat_quota_key(chat_id: ChatId) -> String {
+    format!("chat:{}", chat_id.0)
+}
+
+impl QuotaState {
+    fn limits(&self, key: &str) -> QuotaLimits {
+        self.overrides.get(key).copied().unwrap_or(*DEFAULT_QUOTA_LIMITS)
+    }
+
+    /// Usage for today; counters from a previous day are reset.
+    fn usage_today(&mut self, key: &str, today: &str) -> &mut QuotaUsage {
+        let usage = self.usage.entry(key.to_string()).or_default();
+        if usage.day != today {
+            usage.day = today.to_string();
+            usage.urls_today = 0;
+        }
+        usage
+    }
+}
+
+/// Checks every limit for the requesting user and chat and, if all pass, records the scan and enqueues it.
+/// The quota state stays locked from the check until the job is queued, so concurrent requests
+/// cannot both pass the same limit. Returns the job id and queue position, or the Spanish message
+/// to show when a limit is hit.
+async fn charge_quota_and_enqueue(
+    user: Option<&teloxide::types::User>,
+    chat_id: ChatId,
+    priority: ScanPriority,
+    scan: ScanRequest,
+    apply_cooldown: bool,
+) -> Result<(u64, usize), String> {
+    let requested_by = user.map(|u| u.id);
+    if is_admin(user) {
+        return Ok(enqueue_scan(chat_id, requested_by, priority, scan).await);
+    }
+    let url_count = parse_pattern(&scan.pattern).map_or(0, |p| p.value_count());
+    let mut state = QUOTA_STATE.lock().await;
+    let (user_jobs, chat_jobs) = {
+        let queue = SCAN_QUEUE.lock().await;
+        let jobs = queue.pending.iter().chain(queue.running.iter().map(|(job, _)| job));
+        let (mut user_jobs, mut chat_jobs) = (0, 0);
+        for job in jobs {
+            if user.is_some() && job.requested_by == user.map(|u| u.id) {
+                user_jobs += 1;
+            }
+            if job.owner == chat_id {
+                chat_jobs += 1;
+            }
+        }
+        (user_jobs, chat_jobs)
+    };
+
+    let now = chrono::Utc::now();
+    let today = now.format("%Y-%m-%d").to_string();
+    let mut targets = vec![(chat_quota_key(chat_id), "este chat", chat_jobs)];
+    if let Some(user) = user {
+        targets.insert(0, (user_quota_key(user.id), "tu usuario", user_jobs));
+    }
+
+    for (key, label, active_jobs) in &targets {
+        let limits = state.limits(key);
+        if *active_jobs >= limits.max_jobs {
+            return Err(format!(
+                "🚫 Límite de escaneos simultáneos alcanzado para {} ({}/{}). Espera a que termine alguno (/jobs).",
+                label, active_jobs, limits.max_jobs
+            ));
+        }
+        let usage = state.usage_today(key, &today);
+        if apply_cooldown {
+            if let Some(last) = usage.last_scan_at {
+                let remaining = limits.cooldown_seconds - (now.timestamp() - last);
+                if remaining > 0 {
+                    return Err(format!("⏳ Espera {} s antes de lanzar otro escaneo desde {}.", remaining, label));
+                }
+            }
+        }
+        if usage.urls_today + url_count as u64 > limits.max_urls_per_day {
+            return Err(format!(
+                "🚫 Este escaneo ({} URLs) supera el límite diario de {}: {}/{} URLs usadas hoy. El contador se reinicia a las 00:00 UTC.",
+                url_count, label, usage.urls_today, limits.max_urls_per_day
+            ));
+        }
+    }
+    for (key, _, _) in &targets {
+        let usage = state.usage_today(key, &today);
+        usage.urls_today += url_count as u64;
+        usage.last_scan_at = Some(now.timestamp());
+    }
+    save_json_state(QUOTAS_FILE, &*state);
+    let queued = enqueue_scan(chat_id, requested_by, priority, scan).await;
+    drop(state);
+    Ok(queued)
+}
+
+fn format_quota_line(label: &str, usage: Option<&QuotaUsage>, limits: QuotaLimits, today: &str) -> String {
+    let used = usage.filter(|u| u.day == today).map_or(0, |u| u.urls_today);
+    format!(
+        "{}: {}/{} URLs hoy · máx. {} escaneos simultáneos · espera {} s entre escaneos",
+        label, used, limits.max_urls_per_day, limits.max_jobs, limits.cooldown_seconds
+    )
+}
+
+/// Command `/quota`: shows the caller's and the chat's usage and limits.
+async fn quota_command(bot: Bot, msg: Message) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    if is_admin(msg.from()) {
+        bot.send_message(chat_id, "⭐ Eres administrador: sin límites de escaneo. Usa /quota_set para ajustar límites de otros.").await?;
+        return Ok(());
+    }
+    let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+    let text = {
+        let state = QUOTA_STATE.lock().await;
+        let mut text = String::from("📊 Cuotas de escaneo:");
+        if let Some(user) = msg.from() {
+            let key = user_quota_key(user.id);
+            text.push_str(&format!("\n{}", format_quota_line("Tú", state.usage.get(&key), state.limits(&key), &today)));
+        }
+        let key = chat_quota_key(chat_id);
+        text.push_str(&format!("\n{}", format_quota_line("Este chat", state.usage.get(&key), state.limits(&key), &today)));
+        text
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Command `/quota_set <user|chat> <id> [jobs=N] [urls=N] [cooldown=N] | reset` (admins only).
+async fn quota_set_command(bot: Bot, msg: Message, args: Vec<String>) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    if !is_admin(msg.from()) {
+        bot.send_message(chat_id, "🚫 Solo los administradores pueden cambiar cuotas.").await?;
+        return Ok(());
+    }
+    let usage = "⚠️ Uso: /quota_set <user|chat> <id> [jobs=N] [urls=N] [cooldown=N]\n/quota_set <user|chat> <id> reset";
+    let key = match (args.first().map(String::as_str), args.get(1).and_then(|id| id.parse::<i64>().ok())) {
+        (Some("user"), Some(id)) if id > 0 => user_quota_key(UserId(id as u64)),
+        (Some("chat"), Some(id)) => chat_quota_key(ChatId(id)),
+        _ => {
+            bot.send_message(chat_id, usage).await?;
+            return Ok(());
+        }
+    };
+    let reply = {
+        let mut state = QUOTA_STATE.lock().await;
+        if args.get(2).map(String::as_str) == Some("reset") {
+            state.overrides.remove(&key);
+            save_json_state(QUOTAS_FILE, &*state);
+            format!("✅ Cuota de {} restablecida a los valores por defecto.", key)
+        } else {
+            let mut limits = state.limits(&key);
+            for arg in &args[2..] {
+                let parsed = arg.split_once('=').and_then(|(k, v)| v.parse::<i64>().ok().filter(|v| *v >= 0).map(|v| (k, v)));
+                match parsed {
+                    Some(("jobs", v)) => limits.max_jobs = v as usize,
+                    Some(("urls", v)) => limits.max_urls_per_day = v as u64,
+                    Some(("cooldown", v)) => limits.cooldown_seconds = v,
+                    _ => {
+                        bot.send_message(chat_id, usage).await?;
+                        return Ok(());
+                    }
+                }
+            }
+            state.overrides.insert(key.clone(), limits);
+            save_json_state(QUOTAS_FILE, &*state);
+            format!(
+                "✅ Cuota de {}: {} escaneos simultáneos, {} URLs/día, {} s de espera.",
+                key, limits.max_jobs, limits.max_urls_per_day, limits.cooldown_seconds
+            )
+        }
+    };
+    bot.send_message(chat_id, reply).await?;
+    Ok(())
+}
+
+// --- Saved Patterns ---
+const PATTERNS_FILE: &str = "patterns.json";
+const PATTERN_NAME_MAX_LEN: usize = 32;
+
+/// Preset name -> scan for one chat.
+type ChatPatterns = HashMap<String, ScanRequest>;
+
+// Named scan presets per chat id
+static SAVED_PATTERNS: Lazy<Arc<Mutex<HashMap<i64, ChatPatterns>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(load_json_state(PATTERNS_FILE))));
+
+fn is_valid_pattern_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= PATTERN_NAME_MAX_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+async fn find_saved_pattern(chat_id: ChatId, name: &str) -> Option<ScanRequest> {
+    SAVED_PATTERNS.lock().await.get(&chat_id.0).and_then(|patterns| patterns.get(name)).cloned()
+}
+
+/// Command `/pattern_save <name> <pattern> [site] [excluir=..] [delay=..]`.
+async fn pattern_save_command(bot: Bot, msg: Message, args: Vec<String>) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let usage = "⚠️ Uso: /pattern_save <nombre> <patrón> [sitio] [excluir=a,b] [delay=N]\nEjemplo: /pattern_save diario lb[A]-lb[Z]";
+    let Some((name, rest)) = args.split_first() else {
+        bot.send_message(chat_id, usage).await?;
+        return Ok(());
+    };
+    if !is_valid_pattern_name(name) {
+        bot.send_message(chat_id, "⚠️ Nombre inválido: usa letras, números, '_' o '-' (máx. 32).").await?;
+        return Ok(());
+    }
+    let rest = rest.iter().map(String::as_str).collect::<Vec<_>>();
+    let scan = match ScanRequest::parse(&rest) {
+        Ok(scan) => scan,
+        Err(e) => {
+            bot.send_message(chat_id, format!("⚠️ {}\n{}", e, usage)).await?;
+            return Ok(());
+        }
+    };
+    if let Err(e) = scan.validate() {
+        bot.send_message(chat_id, format!("⚠️ {}", e)).await?;
+        return Ok(());
+    }
+    let description = scan.describe();
+    {
+        let mut patterns = SAVED_PATTERNS.lock().await;
+        patterns.entry(chat_id.0).or_default().insert(name.clone(), scan);
+        save_json_state(PATTERNS_FILE, &*patterns);
+    }
+    bot.send_message(chat_id, format!("💾 Patrón '{}' guardado: {}\nEjecútalo con /run {}", name, description, name)).await?;
+    Ok(())
+}
+
+/// Command `/pattern_list`: lists the chat's saved presets.
+async fn pattern_list_command(bot: Bot, msg: Message) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let patterns = SAVED_PATTERNS.lock().await.get(&chat_id.0).cloned().unwrap_or_default();
+    if patterns.is_empty() {
+        bot.send_message(chat_id, "ℹ️ No hay patrones guardados. Usa /pattern_save <nombre> <patrón> [sitio].").await?;
+        return Ok(());
+    }
+    let mut names = patterns.keys().collect::<Vec<_>>();
+    names.sort();
+    let mut text = String::from("📚 Patrones guardados:");
+    for name in names {
+        text.push_str(&format!("\n• {} → {}", name, patterns[name].describe()));
+    }
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Command `/pattern_delete <name>`.
+async fn pattern_delete_command(bot: Bot, msg: Message, name: String) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let removed = {
+        let mut patterns = SAVED_PATTERNS.lock().await;
+        let removed = patterns.get_mut(&chat_id.0).and_then(|p| p.remove(&name)).is_some();
+        if removed {
+            patterns.retain(|_, p| !p.is_empty());
+            save_json_state(PATTERNS_FILE, &*patterns);
+        }
+        removed
+    };
+    let reply = if removed { format!("🗑️ Patrón '{}' eliminado.", name) } else { format!("ℹ️ No existe el patrón '{}'.", name) };
+    bot.send_message(chat_id, reply).await?;
+    Ok(())
+}
+
+/// Command `/run <name>`: launches a saved preset.
+async fn run_command(bot: Bot, msg: Message, name: String) -> ResponseResult<()> {
+    match find_saved_pattern(msg.chat.id, &name).await {
+        Some(scan) => submit_scan(bot, &msg, scan).await,
+        None => {
+            bot.send_message(msg.chat.id, format!("ℹ️ No existe el patrón '{}'. Consulta /pattern_list.", name)).await?;
+            Ok(())
+        }
+    }
+}
+
+// --- Scheduled Scans ---
+// Saved patterns can be launched by cron expressions (standard 5 fields) evaluated in the chat's timezone.
+const SCHEDULES_FILE: &str = "schedules.json";
+const SCHEDULER_TICK_SECONDS: u64 = 30;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ScheduledScan {
+    id: u32,
+    chat_id: i64,
+    /// Name of the saved pattern; resolved at run time so later edits apply.
+    pattern_name: String,
+    cron: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SchedulerState {
+    next_id: u32,
+    scans: Vec<ScheduledScan>,
+    /// IANA timezone name per chat id; chats without one use UTC.
+    timezones: HashMap<i64, String>,
+}
+
+static SCHEDULER_STATE: Lazy<Arc<Mutex<SchedulerState>>> =
+    Lazy::new(|| Arc::new(Mutex::new(load_json_state(SCHEDULES_FILE))));
+
+fn parse_cron(expression: &str) -> Result<croner::Cron, String> {
+    if expression.split_whitespace().count() != 5 {
+        return Err("La expresión cron debe tener 5 campos: minuto hora día mes día_semana".to_string());
+    }
+    croner::Cron::new(expression).parse().map_err(|e| format!("Expresión cron inválida: {:?}", e))
+}
+
+fn chat_timezone(state: &SchedulerState, chat_id: i64) -> chrono_tz::Tz {
+    state.timezones.get(&chat_id).and_then(|tz| tz.parse().ok()).unwrap_or(chrono_tz::UTC)
+}
+
+fn next_run_after(scan: &ScheduledScan, tz: chrono_tz::Tz, after: chrono::DateTime<chrono::Utc>) -> Option<chrono::DateTime<chrono_tz::Tz>> {
+    let cron = parse_cron(&scan.cron).ok()?;
+    cron.find_next_occurrence(&after.with_timezone(&tz), false).ok()
+}
+
+/// Background loop that launches scheduled scans whose next occurrence has passed.
+async fn run_scan_scheduler(bot: Bot) {
+    let mut interval = tokio::time::interval(Duration::from_secs(SCHEDULER_TICK_SECONDS));
+    let mut last_check = chrono::Utc::now();
+    loop {
+        interval.tick().await;
+        let now = chrono::Utc::now();
+        let due = {
+            let state = SCHEDULER_STATE.lock().await;
+            state
+                .scans
+                .iter()
+                .filter(|scan| {
+                    next_run_after(scan, chat_timezone(&state, scan.chat_id), last_check)
+                        .is_some_and(|next| next.with_timezone(&chrono::Utc) <= now)
+                })
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        last_check = now;
+
+        for scheduled in due {
+            let chat_id = ChatId(scheduled.chat_id);
+            let Some(scan) = find_saved_pattern(chat_id, &scheduled.pattern_name).await else {
+                error!("Programación #{} apunta al patrón inexistente '{}'", scheduled.id, scheduled.pattern_name);
+                let _ = bot
+                    .send_message(chat_id, format!("⚠️ La programación #{} no pudo ejecutarse: el patrón '{}' ya no existe.", scheduled.id, scheduled.pattern_name))
+                    .await;
+                continue;
+            };
+            if let Err(e) = charge_quota_and_enqueue(None, chat_id, ScanPriority::Normal, scan, false).await {
+                let _ = bot.send_message(chat_id, format!("⏰ Programación #{} omitida.\n{}", scheduled.id, e)).await;
+                continue;
+            }
+            info!("Escaneo programado #{} ('{}') encolado para chat {}", scheduled.id, scheduled.pattern_name, chat_id);
+        }
+    }
+}
+
+/// Command `/schedule add <pattern_name> <cron>` | `/schedule list` | `/schedule delete <id>`.
+async fn schedule_command(bot: Bot, msg: Message, args: Vec<String>) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let usage = "⚠️ Uso:\n/schedule add <patrón_guardado> <min> <hora> <día> <mes> <día_semana>\n/schedule list\n/schedule delete <id>\nEjemplo: /schedule add diario 0 20 * * 1-5";
+    match args.first().map(String::as_str) {
+        Some("add") if args.len() >= 3 => {
+            let pattern_name = &args[1];
+            let expression = args[2..].join(" ");
+            if find_saved_pattern(chat_id, pattern_name).await.is_none() {
+                bot.send_message(chat_id, format!("ℹ️ No existe el patrón '{}'. Guárdalo antes con /pattern_save.", pattern_name)).await?;
+                return Ok(());
+            }
+            if let Err(e) = parse_cron(&expression) {
+                bot.send_message(chat_id, format!("⚠️ {}", e)).await?;
+                return Ok(());
+            }
+            let reply = {
+                let mut state = SCHEDULER_STATE.lock().await;
+                state.next_id += 1;
+                let scan = ScheduledScan { id: state.next_id, chat_id: chat_id.0, pattern_name: pattern_name.clone(), cron: expression };
+                let tz = chat_timezone(&state, chat_id.0);
+                let next = next_run_after(&scan, tz, chrono::Utc::now())
+                    .map(|next| next.format("%Y-%m-%d %H:%M %Z").to_string())
+                    .unwrap_or_else(|| "nunca".to_string());
+                let reply = format!("⏰ Programación #{} creada para '{}' ({}). Próxima ejecución: {}", scan.id, scan.pattern_name, scan.cron, next);
+                state.scans.push(scan);
+                save_json_state(SCHEDULES_FILE, &*state);
+                reply
+            };
+            bot.send_message(chat_id, reply).await?;
+        }
+        Some("list") => {
+            let text = {
+                let state = SCHEDULER_STATE.lock().await;
+                let tz = chat_timezone(&state, chat_id.0);
+                let now = chrono::Utc::now();
+                let mut text = format!("⏰ Escaneos programados (zona horaria: {}):", tz);
+                let mut any = false;
+                for scan in state.scans.iter().filter(|scan| scan.chat_id == chat_id.0) {
+                    any = true;
+                    let next = next_run_after(scan, tz, now)
+                        .map(|next| next.format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_else(|| "nunca".to_string());
+                    text.push_str(&format!("\n#{} · {} · `{}` · próxima: {}", scan.id, scan.pattern_name, scan.cron, next));
+                }
+                if !any {
+                    text.push_str("\nNinguno. Crea uno con /schedule add.");
+                }
+                text
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        Some("delete") if args.len() == 2 => {
+            let Ok(id) = args[1].trim_start_matches('#').parse::<u32>() else {
+                bot.send_message(chat_id, usage).await?;
+                return Ok(());
+            };
+            let removed = {
+                let mut state = SCHEDULER_STATE.lock().await;
+                let before = state.scans.len();
+                state.scans.retain(|scan| !(scan.id == id && scan.chat_id == chat_id.0));
+                let removed = state.scans.len() != before;
+                if removed {
+                    save_json_state(SCHEDULES_FILE, &*state);
+                }
+                removed
+            };
+            let reply = if removed { format!("🗑️ Programación #{} eliminada.", id) } else { format!("ℹ️ No existe la programación #{}.", id) };
+            bot.send_message(chat_id, reply).await?;
+        }
+        _ => {
+            bot.send_message(chat_id, usage).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Command `/timezone [IANA name]`: shows or sets the chat's timezone for scheduled scans.
+async fn timezone_command(bot: Bot, msg: Message, tz_name: String) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let reply = {
+        let mut state = SCHEDULER_STATE.lock().await;
+        if tz_name.is_empty() {
+            format!("🕒 Zona horaria del chat: {}. Cámbiala con /timezone Europe/Madrid", chat_timezone(&state, chat_id.0))
+        } else {
+            match tz_name.parse::<chrono_tz::Tz>() {
+                Ok(tz) => {
+                    state.timezones.insert(chat_id.0, tz.name().to_string());
+                    save_json_state(SCHEDULES_FILE, &*state);
+                    format!("✅ Zona horaria establecida: {}", tz)
+                }
+                Err(_) => format!("⚠️ Zona horaria desconocida: {}. Usa nombres IANA como America/Mexico_City.", tz_name),
+            }
+        }
+    };
+    bot.send_message(chat_id, reply).await?;
+    Ok(())
+}
+
+// --- Short-link Expansion ---
+// Links are resolved through their HTTP redirect chain so shorteners reveal the real destination.
+const EXPANSION_MAX_HOPS: usize = 10;
+const EXPANSION_CACHE_MAX_ENTRIES: usize = 5000;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ExpandedLink {
+    final_url: String,
+    hops: usize,
+    /// False when the chain hit the hop limit or looped.
+    complete: bool,
+}
+
+impl ExpandedLink {
+    fn final_host(&self) -> Option<String> {
+        Url::parse(&self.final_url).ok().and_then(|u| u.host_str().map(ToOwned::to_owned))
+    }
+}
+
+// Resolved links by original URL; shorteners rarely change targets so entries never expire
+static EXPANDED_LINK_CACHE: Lazy<Arc<Mutex<HashMap<String, ExpandedLink>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// Follows the redirect chain of `link` (HEAD, falling back to GET) up to `EXPANSION_MAX_HOPS`.
+async fn expand_link(link: &str) -> Option<ExpandedLink> {
+    if let Some(cached) = EXPANDED_LINK_CACHE.lock().await.get(link).cloned() {
+        return Some(cached);
+    }
+    let url = Url::parse(link).ok()?;
+    let policy = RedirectPolicy { max_hops: EXPANSION_MAX_HOPS, ..RedirectPolicy::default() };
+    let mut followed = send_following_redirects(reqwest::Request::new(reqwest::Method::HEAD, url.clone()), &policy, None).await;
+    if followed.as_ref().is_ok_and(|f| f.response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED) {
+        followed = send_following_redirects(reqwest::Request::new(reqwest::Method::GET, url), &policy, None).await;
+    }
+    let followed = match followed {
+        Ok(followed) => followed,
+        Err(e) => {
+            debug!("No se pudo expandir {}: {}", link, e);
+            return None;
+        }
+    };
+    let expanded = ExpandedLink {
+        final_url: followed.final_url().to_string(),
+        hops: followed.chain.len() - 1,
+        // A redirect status at the end means we stopped on the hop limit or a loop
+        complete: !followed.response.status().is_redirection(),
+    };
+    let mut cache = EXPANDED_LINK_CACHE.lock().await;
+    if cache.len() >= EXPANSION_CACHE_MAX_ENTRIES {
+        cache.clear();
+    }
+    cache.insert(link.to_string(), expanded.clone());
+    Some(expanded)
+}
+
+/// Expands up to `LIVENESS_MAX_LINKS` links concurrently; links that resolve to themselves are left out.
+async fn expand_links(links: &[String]) -> HashMap<String, ExpandedLink> {
+    let mut tasks = tokio::task::JoinSet::new();
+    for link in links.iter().take(LIVENESS_MAX_LINKS).cloned() {
+        tasks.spawn(async move {
+            let expanded = expand_link(&link).await;
+            (link, expanded)
+        });
     }
-    Ok(())
+    let mut expanded_links = HashMap::new();
+    while let Some(result) = tasks.join_next().await {
+        if let Ok((link, Some(expanded))) = result {
+            if expanded.hops > 0 || !expanded.complete {
+                expanded_links.insert(link, expanded);
+            }
+        }
+    }
+    expanded_links
 }
 
-/// Command `/yts_stop`: Unsubscribes the chat from YTS movie notifications.
-async fn yts_stop_command(bot: Bot, msg: Message) -> ResponseResult<()> {
-    let chat_id = msg.chat.id;
-    let mut unsubscribed = false;
-    {
-        let mut chats = SUBSCRIBED_CHAT_IDS.lock().await;
-        if let Some(pos) = chats.iter().position(|&id| id == chat_id) {
-            chats.remove(pos);
-            unsubscribed = true;
+// --- Link Liveness ---
+// Extracted download links are probed so hits show which ones still work.
+const LIVENESS_MAX_LINKS: usize = 20;
+const LIVENESS_BODY_MAX_BYTES: usize = 256 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum LinkStatus {
+    Alive,
+    Dead,
+    /// Blocked, rate limited, timed out or otherwise inconclusive.
+    Unknown,
+}
+
+impl LinkStatus {
+    fn icon(self) -> &'static str {
+        match self {
+            LinkStatus::Alive => "🟢",
+            LinkStatus::Dead => "🔴",
+            LinkStatus::Unknown => "⚪",
         }
     }
+}
 
-    if unsubscribed {
-        bot.send_message(chat_id, "✅ Te has dado de baja de las notificaciones de YTS.").await?;
-        info!("Chat {} dado de baja de notificaciones YTS.", chat_id);
+// Hosts that answer 200 with a "file not found" page; their body is checked for these markers.
+const HOST_DEAD_MARKERS: &[(&str, &[&str])] = &[
+    ("mediafire.com", &["File Removed", "Invalid or Deleted File", "file you requested has been removed"]),
+    ("drive.google.com", &["the file you have requested does not exist", "el archivo que has solicitado no existe"]),
+    ("1fichier.com", &["The requested file does not exist", "has been deleted"]),
+    ("uptobox.com", &["File not found", "Fichier introuvable"]),
+    ("pixeldrain.com", &["File not found", "this file has been removed"]),
+    ("zippyshare.com", &["File does not exist on this server", "File has expired"]),
+];
+// Markers that mean a dead file on hosts without their own list. Only short bodies are checked:
+// a soft-404 page is small, while a long live page may just mention these words.
+const GENERIC_DEAD_MARKERS: &[&str] = &["404 Not Found", "File not found", "Archivo no encontrado", "file has been removed"];
+const GENERIC_DEAD_MARKERS_MAX_BODY_BYTES: usize = 16 * 1024;
 
-        // Optional: Stop the monitor task if no chats are subscribed.
-        // This might be desirable to save resources if the bot has other functions.
-        let chats = SUBSCRIBED_CHAT_IDS.lock().await;
-        if chats.is_empty() {
-            info!("No hay chats suscritos. Intentando detener la tarea de monitorización de YTS...");
-            let mut task_handle_guard = YTS_MONITOR_TASK_HANDLE.lock().await;
-            if let Some(handle) = task_handle_guard.take() { // .take() removes the handle
-                handle.abort(); // Abort the task
-                info!("Tarea de monitorización de YTS detenida ya que no hay suscriptores.");
-                 // Notify admin or log, don't message user as they just unsubscribed.
-            }
+fn host_dead_markers(host: &str) -> Option<&'static [&'static str]> {
+    HOST_DEAD_MARKERS
+        .iter()
+        .find(|(suffix, _)| host == *suffix || host.ends_with(&format!(".{}", suffix)))
+        .map(|(_, markers)| *markers)
+}
+
+/// `🟢 https://bit.ly/x → mega.nz`; the expanded host is only shown when it differs from the link's own.
+fn format_link_line(link: &str, status: Option<&LinkStatus>, expanded: Option<&ExpandedLink>) -> String {
+    let mut line = format!("{} {}", status.map_or("•", |s| s.icon()), link);
+    if let Some(expanded) = expanded {
+        let original_host = Url::parse(link).ok().and_then(|u| u.host_str().map(ToOwned::to_owned));
+        if let Some(final_host) = expanded.final_host().filter(|host| Some(host) != original_host.as_ref()) {
+            line.push_str(&format!(" → {}", final_host));
+        }
+        if !expanded.complete {
+            line.push_str(" (⚠️ redirección sin resolver)");
         }
-    } else {
-        bot.send_message(chat_id, "ℹ️ No estabas suscrito a las notificaciones de YTS.").await?;
     }
-    Ok(())
+    line
 }
 
+fn format_liveness_counts(statuses: &HashMap<String, LinkStatus>) -> String {
+    let count = |wanted: LinkStatus| statuses.values().filter(|s| **s == wanted).count();
+    format!(
+        "{} {} · {} {} · {} {}",
+        LinkStatus::Alive.icon(), count(LinkStatus::Alive),
+        LinkStatus::Dead.icon(), count(LinkStatus::Dead),
+        LinkStatus::Unknown.icon(), count(LinkStatus::Unknown)
+    )
+}
 
-// --- Existing Functions (modified slightly for safety or clarity if needed) ---
-fn parse_pattern(input: &str) -> Option<ScanPattern> { // Existing
-    let parts: Vec<&str> = input.split('-').collect();
-    if parts.len() != 2 {
-        return None;
+/// Probes one link: HEAD for ordinary hosts, GET + body markers for hosts known to fake a 200.
+async fn probe_link_liveness(link: &str) -> LinkStatus {
+    let Ok(url) = Url::parse(link) else { return LinkStatus::Dead };
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    let host_markers = host_dead_markers(&host);
+    let method = if host_markers.is_some() { reqwest::Method::GET } else { reqwest::Method::HEAD };
+
+    let followed = match send_following_redirects(reqwest::Request::new(method.clone(), url.clone()), &RedirectPolicy::default(), None).await {
+        Ok(followed) => followed,
+        Err(e) => {
+            debug!("Comprobación de enlace {} fallida: {}", link, e);
+            return LinkStatus::Unknown;
+        }
+    };
+    let status = followed.response.status();
+    if method == reqwest::Method::HEAD && (status == reqwest::StatusCode::METHOD_NOT_ALLOWED || status == reqwest::StatusCode::NOT_IMPLEMENTED || status == reqwest::StatusCode::FORBIDDEN) {
+        // Some file hosts reject HEAD outright; retry the same check with a GET
+        return match send_following_redirects(reqwest::Request::new(reqwest::Method::GET, url), &RedirectPolicy::default(), None).await {
+            Ok(followed) => classify_link_response(followed, host_markers, true).await,
+            Err(_) => LinkStatus::Unknown,
+        };
     }
-    let parse_part = |s: &str| -> Option<(char, String)> {
-        let start_idx = s.find('[')?;
-        let end_idx = s.find(']')?;
-        if start_idx >= end_idx { return None; } // Ensure valid indices
-        let variable_char = s.get(start_idx + 1..end_idx)?.chars().next()?;
-        let prefix = s.get(..start_idx)?.to_string();
-        let suffix = s.get(end_idx + 1..)?.to_string();
-        Some((variable_char, format!("{}{{}}{}", prefix, suffix)))
+    classify_link_response(followed, host_markers, method == reqwest::Method::GET).await
+}
+
+/// Status-based verdict, refined by scanning the body for dead-file markers when `read_body` is set.
+async fn classify_link_response(followed: FollowedResponse, host_markers: Option<&[&str]>, read_body: bool) -> LinkStatus {
+    let status = followed.response.status();
+    if status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::GONE {
+        return LinkStatus::Dead;
+    }
+    if !status.is_success() {
+        return LinkStatus::Unknown;
+    }
+    if !read_body {
+        return LinkStatus::Alive;
+    }
+    // Read chunk by chunk and stop at the cap: file hosts may serve the whole file here
+    let mut response = followed.response;
+    let mut body = Vec::new();
+    while body.len() < LIVENESS_BODY_MAX_BYTES {
+        match response.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            Ok(None) => break,
+            Err(_) => return LinkStatus::Unknown,
+        }
+    }
+    body.truncate(LIVENESS_BODY_MAX_BYTES);
+    let markers = match host_markers {
+        Some(markers) => markers,
+        None if body.len() <= GENERIC_DEAD_MARKERS_MAX_BODY_BYTES => GENERIC_DEAD_MARKERS,
+        None => &[],
     };
-    let (start_char, start_tmpl) = parse_part(parts[0])?;
-    let (end_char, end_tmpl) = parse_part(parts[1])?;
-    if start_tmpl != end_tmpl { // This check might be too restrictive if templates are meant to differ
-        return None;
+    let body = String::from_utf8_lossy(&body).to_lowercase();
+    let dead = markers.iter().any(|marker| body.contains(&marker.to_lowercase()));
+    if dead { LinkStatus::Dead } else { LinkStatus::Alive }
+}
+
+/// Checks up to `LIVENESS_MAX_LINKS` links concurrently (each host is still paced on its own).
+/// Already-expanded links are probed at their final destination to skip the shortener hop.
+async fn check_links_liveness(links: &[String], expanded: &HashMap<String, ExpandedLink>) -> HashMap<String, LinkStatus> {
+    let mut tasks = tokio::task::JoinSet::new();
+    for link in links.iter().take(LIVENESS_MAX_LINKS).cloned() {
+        let target = expanded.get(&link).filter(|e| e.complete).map_or_else(|| link.clone(), |e| e.final_url.clone());
+        tasks.spawn(async move {
+            let status = probe_link_liveness(&target).await;
+            (link, status)
+        });
     }
-    Some(ScanPattern {
-        start_char,
-        end_char,
-        template: start_tmpl,
-    })
+    let mut statuses = HashMap::new();
+    while let Some(result) = tasks.join_next().await {
+        if let Ok((link, status)) = result {
+            statuses.insert(link, status);
+        }
+    }
+    statuses
 }
 
-async fn check_links(bot: Bot, msg: Message, pattern: String) -> ResponseResult<()> { // Existing
+/// Command `/recheck <id|url>`: re-probes the links of an archived hit.
+async fn recheck_command(bot: Bot, msg: Message, key: String) -> ResponseResult<()> {
     let chat_id = msg.chat.id;
-    let scan_pattern = match parse_pattern(&pattern) {
-        Some(p) => p,
-        None => {
-            bot.send_message(chat_id, "⚠️ Formato de patrón inválido. Ejemplos:\n/check l[c]a-l[m]a\n/check [A]bc-[Z]bc")
-                .await?;
-            return Ok(());
-        }
+    let Some(mut hit) = find_archived_hit(chat_id, &key).await else {
+        bot.send_message(chat_id, format!("ℹ️ No hay ningún hallazgo archivado para: {}", key)).await?;
+        return Ok(());
     };
+    if hit.links.is_empty() {
+        bot.send_message(chat_id, format!("ℹ️ El hallazgo '{}' no tiene enlaces.", hit.title)).await?;
+        return Ok(());
+    }
+    bot.send_message(chat_id, format!("🔄 Comprobando {} enlace(s) de '{}'...", hit.links.len().min(LIVENESS_MAX_LINKS), hit.title)).await?;
+    hit.link_status = check_links_liveness(&hit.links, &hit.expanded_links).await;
+    hit.links_checked_at = Some(chrono::Utc::now().timestamp());
 
-    bot.send_message(chat_id, &format!("🔍 Escaneando con patrón: {}...", pattern)).await?;
-    let start_byte = scan_pattern.start_char as u8;
-    let end_byte = scan_pattern.end_char as u8;
-    let (eff_start, eff_end) = if start_byte <= end_byte { (start_byte, end_byte) } else { (end_byte, start_byte) };
+    let mut text = format!("🔗 {}\n{}", hit.title, format_liveness_counts(&hit.link_status));
+    for link in &hit.links {
+        text.push_str(&format!("\n{}", format_link_line(link, hit.link_status.get(link), hit.expanded_links.get(link))));
+    }
+    {
+        let mut index = ARCHIVE_INDEX.lock().await;
+        index.insert(archive_key(chat_id, &hit.id), hit);
+        save_json_state(ARCHIVE_INDEX_FILE, &*index);
+    }
+    if text.chars().count() > TELEGRAM_MESSAGE_LIMIT {
+        text = text.chars().take(TELEGRAM_MESSAGE_LIMIT - 1).collect::<String>() + "…";
+    }
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
 
-    for c_byte in eff_start..=eff_end {
-        let current_char = c_byte as char;
-        // Ensure char is valid before forming URL, especially if range is large
-        if !current_char.is_alphanumeric() && !"[](){}".contains(current_char) { // Example filter, adjust as needed
-             // debug!("Skipping non-alphanumeric char in pattern scan: {}", current_char);
-             // continue;
-        }
+// --- Hit Archive ---
+// Raw bodies of hits are stored under data/archive/ so they survive the paste being deleted.
+// The index is keyed by "<chat id>:<archive id>": each chat only sees its own hits.
+const ARCHIVE_DIR: &str = "archive";
+const ARCHIVE_INDEX_FILE: &str = "archive_index.json";
 
-        let param_val = scan_pattern.template.replace("{}", &current_char.to_string());
-        let url = format!("{}{}", BASE_URL, param_val);
-        
-        info!("Verificando URL (scraper): {}", url);
-        
-        match check_page(&url).await {
-            Ok(Some(title)) => {
-                if title != TEXTO_NO_DESEADO && !title.trim().is_empty() { // Added !title.trim().is_empty()
-                    let message_text = format!("✅ ¡Encontrado!\nURL: {}\nTítulo: {}", url, title);
-                    bot.send_message(chat_id, &message_text).await?;
-                } else {
-                    debug!("Título no deseado o vacío encontrado en {}: '{}'", url, title);
-                }
-            }
-            Ok(None) => {
-                debug!("No se encontró título en {}", url);
-                continue;
-            }
-            Err(e) => {
-                error!("Error al verificar la página {} (scraper): {}", url, e);
-                // Optionally notify user about specific page error, or just log
-                // bot.send_message(chat_id, format!("⚠️ Error al verificar {}", url)).await?;
-                continue;
-            }
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ArchivedHit {
+    id: String,
+    url: String,
+    final_url: String,
+    title: String,
+    archived_at: i64,
+    file_name: String,
+    links: Vec<String>,
+    #[serde(default)]
+    link_status: HashMap<String, LinkStatus>,
+    /// When `link_status` was last refreshed (unix seconds).
+    #[serde(default)]
+    links_checked_at: Option<i64>,
+    #[serde(default)]
+    expanded_links: HashMap<String, ExpandedLink>,
+}
+
+static ARCHIVE_INDEX: Lazy<Arc<Mutex<HashMap<String, ArchivedHit>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(load_json_state(ARCHIVE_INDEX_FILE))));
+
+/// Archive ids are `<site>_<value>`, restricted to filename-safe characters.
+fn archive_id(profile: &SiteProfile, value: &str) -> String {
+    let readable = format!("{}_{}", profile.name, value)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect::<String>();
+    // The readable part is lossy ("a.b" and "a/b" both give "a_b"); a hash of the raw pair keeps ids distinct
+    let raw = format!("{}\0{}", profile.name, value);
+    let hash = raw.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |h, b| (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3));
+    format!("{}-{:08x}", readable, hash as u32)
+}
+
+/// Index key of a chat's archived hit; archives are private to the chat that found them.
+fn archive_key(chat_id: ChatId, id: &str) -> String {
+    format!("{}:{}", chat_id.0, id)
+}
+
+/// Stores the hit's raw body and links; re-scanning the same value replaces the previous snapshot.
+async fn archive_hit(chat_id: ChatId, profile: &SiteProfile, value: &str, url: &str, hit: &PageHit) -> Option<String> {
+    let id = archive_id(profile, value);
+    let extension = if profile.mode == ScanMode::Json { "json" } else { "html" };
+    let file_name = format!("{}_{}.{}", chat_id.0, id, extension);
+    let path = data_path(ARCHIVE_DIR).join(&file_name);
+    if let Err(e) = tokio::fs::create_dir_all(data_path(ARCHIVE_DIR)).await {
+        error!("No se pudo crear el directorio de archivo: {}", e);
+        return None;
+    }
+    if let Err(e) = tokio::fs::write(&path, &hit.body).await {
+        error!("No se pudo archivar {} en {}: {}", url, path.display(), e);
+        return None;
+    }
+    let entry = ArchivedHit {
+        id: id.clone(),
+        url: url.to_string(),
+        final_url: hit.final_url.to_string(),
+        title: hit.title.clone(),
+        archived_at: chrono::Utc::now().timestamp(),
+        file_name,
+        links: hit.links.clone(),
+        link_status: hit.link_status.clone(),
+        links_checked_at: Some(chrono::Utc::now().timestamp()),
+        expanded_links: hit.expanded_links.clone(),
+    };
+    let mut index = ARCHIVE_INDEX.lock().await;
+    index.insert(archive_key(chat_id, &id), entry);
+    save_json_state(ARCHIVE_INDEX_FILE, &*index);
+    Some(id)
+}
+
+/// Finds an archived hit by id, scanned URL or final URL.
+async fn find_archived_hit(chat_id: ChatId, key: &str) -> Option<ArchivedHit> {
+    let index = ARCHIVE_INDEX.lock().await;
+    let prefix = archive_key(chat_id, "");
+    let own_hits = || index.iter().filter(|(k, _)| k.starts_with(&prefix)).map(|(_, hit)| hit);
+    if let Some(hit) = index.get(&archive_key(chat_id, key)) {
+        return Some(hit.clone());
+    }
+    if let Some(hit) = own_hits().find(|hit| hit.url == key || hit.final_url == key) {
+        return Some(hit.clone());
+    }
+    // The readable part of an id (without its "-<hash>" suffix) also works while it is unambiguous
+    let mut readable = own_hits().filter(|hit| hit.id.rsplit_once('-').is_some_and(|(readable, _)| readable == key));
+    match (readable.next(), readable.next()) {
+        (Some(hit), None) => Some(hit.clone()),
+        _ => None,
+    }
+}
+
+fn format_timestamp(unix_seconds: i64) -> String {
+    chrono::DateTime::from_timestamp(unix_seconds, 0)
+        .map(|dt| dt.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
+
+/// Command `/archive <url|id>`: sends the stored snapshot of a hit back as a document.
+async fn archive_command(bot: Bot, msg: Message, key: String) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let Some(hit) = find_archived_hit(chat_id, &key).await else {
+        bot.send_message(chat_id, format!("ℹ️ No hay ninguna copia archivada para: {}", key)).await?;
+        return Ok(());
+    };
+    let mut caption = format!(
+        "🗄️ {}\nURL: {}\nArchivado: {}",
+        hit.title, hit.url, format_timestamp(hit.archived_at)
+    );
+    if !hit.links.is_empty() {
+        caption.push_str(&format!("\nEnlaces ({}):", hit.links.len()));
+        for link in &hit.links {
+            caption.push_str(&format!("\n{}", format_link_line(link, hit.link_status.get(link), hit.expanded_links.get(link))));
         }
-        tokio::time::sleep(Duration::from_secs(2)).await; // Keep delay to be polite to server
     }
-    bot.send_message(chat_id, "🚀 Escaneo (scraper) completado!").await?;
+    if caption.chars().count() > TELEGRAM_CAPTION_LIMIT {
+        caption = caption.chars().take(TELEGRAM_CAPTION_LIMIT - 1).collect::<String>() + "…";
+    }
+    let path = data_path(ARCHIVE_DIR).join(&hit.file_name);
+    bot.send_document(chat_id, InputFile::file(path).file_name(hit.file_name.clone()))
+        .caption(caption)
+        .await?;
     Ok(())
 }
 
-async fn check_page(url: &str) -> Result<Option<String>, reqwest::Error> { // Existing
-    let res = reqwest::get(url).await?;
-    if !res.status().is_success() {
-        error!("HTTP error {} for URL: {}", res.status(), url);
-        return Ok(None); // Or return specific error
+/// Routes inline keyboard presses by the prefix of their callback data.
+async fn callback_query_handler(bot: Bot, q: CallbackQuery) -> ResponseResult<()> {
+    let data = q.data.clone().unwrap_or_default();
+    if let Some(payload) = data.strip_prefix(YTS_TORRENT_CALLBACK_PREFIX) {
+        return yts_torrent_callback(bot, q, payload).await;
     }
-    let html_content = res.text().await?;
-    let document = Html::parse_document(&html_content);
-    let title_selector = Selector::parse("title").unwrap(); // .unwrap() is okay if "title" is always valid selector
-
-    Ok(document
-        .select(&title_selector)
-        .next()
-        .map(|title_element| title_element.text().collect::<String>().trim().to_string())
-        .filter(|t| !t.is_empty())) // Ensure title is not just whitespace
+    if let Some(payload) = data.strip_prefix(YTS_SEARCH_PAGE_CALLBACK_PREFIX) {
+        return yts_search_page_callback(bot, q, payload).await;
+    }
+    if let Some(payload) = data.strip_prefix(YTS_DETAILS_CALLBACK_PREFIX) {
+        return yts_details_callback(bot, q, payload).await;
+    }
+    bot.answer_callback_query(q.id).await?;
+    Ok(())
 }
 
 async fn start_command(bot: Bot, msg: Message) -> ResponseResult<()> { // Renamed from `start` for clarity
     let help_text = "¡Bienvenido al Scraper Avanzado y Notificador YTS! 🕷️🎬\n\n\
 Comandos disponibles:\n
 /start - Muestra esta ayuda.
-/check [patrón] - Inicia escaneo de links (función original). Ej: /check l[c]a-l[m]a
+/check [patrón] [sitio] - Inicia escaneo de links (función original). Ej: /check l[c]a-l[m]a
+/archive [url|id] - Envía la copia archivada de un hallazgo.
+/recheck [url|id] - Vuelve a comprobar si los enlaces de un hallazgo siguen vivos.
+/pattern_save [nombre] [patrón] [sitio] - Guarda un escaneo con nombre (opciones: excluir=a,b delay=N).
+/pattern_list - Lista los patrones guardados.
+/pattern_delete [nombre] - Elimina un patrón guardado.
+/run [nombre] - Ejecuta un patrón guardado.
+/jobs - Muestra los escaneos en ejecución y la posición en la cola.
+/quota - Muestra tus cuotas de escaneo y las del chat.
+/schedule add [nombre] [cron] - Programa un patrón guardado. Ej: /schedule add diario 0 20 * * 1-5
+/schedule list - Lista los escaneos programados y su próxima ejecución.
+/schedule delete [id] - Elimina un escaneo programado.
+/timezone [zona] - Muestra o cambia la zona horaria del chat. Ej: /timezone Europe/Madrid
 /yts_init - Suscribe este chat a notificaciones de nuevas películas de YTS.
-/yts_stop - Da de baja este chat de las notificaciones de YTS.";
+/yts_stop - Da de baja este chat de las notificaciones de YTS.
+/yts_filter [clave=valor...] - Muestra o ajusta los filtros YTS del chat (calidad, rating, genero, año, idioma, duracion). Ej: /yts_filter calidad=1080p rating=6.5
+/yts_policy [clave=valor...] - Anuncia un solo torrent elegido por preferencias (calidades, fuente, x265, seeds, max). Ej: /yts_policy calidades=1080p,720p fuente=bluray max=4GB
+/yts_search [texto] - Busca en el catálogo de YTS (opciones: calidad= genero= rating= orden=). Ej: /yts_search alien rating=7
+/yts_movie [id|imdb] - Ficha completa de una película con reparto, torrents y títulos relacionados. Ej: /yts_movie tt0078748
+/want [título año|imdb] - Avisa cuando una película aparezca en YTS. Ej: /want Dune 2021
+/wants - Muestra la lista de deseos del chat.
+/unwant [id] - Quita una película de la lista de deseos.";
     bot.send_message(msg.chat.id, help_text).await?;
     Ok(())
 }
@@ -441,8 +4245,20 @@ async fn main() {
     // Bot methods are now directly awaitable.
     let bot = Bot::from_env(); 
 
+    // Scheduled scans and the scan queue run on the same runtime as the dispatcher
+    tokio::spawn(run_scan_queue(bot.clone()));
+    tokio::spawn(run_scan_scheduler(bot.clone()));
+    tokio::spawn(run_watchlist_search(bot.clone()));
+
+    // Resume YTS notifications for chats that were subscribed before the restart
+    let subscribers = SUBSCRIBED_CHAT_IDS.lock().await.len();
+    if subscribers > 0 || watchlist_has_wants().await {
+        info!("Restaurados {} chats suscritos a YTS y las listas de deseos; reanudando el monitor.", subscribers);
+        ensure_yts_monitor_running(&bot).await;
+    }
+
     // Define command handlers using dptree branches, similar to the original structure
-    let handler = Update::filter_message()
+    let message_handler = Update::filter_message()
         .branch( // /start command
             dptree::entry()
                 .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
@@ -454,12 +4270,18 @@ async fn main() {
                 .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
                 .filter(|text: String| text.starts_with("/check "))
                 .endpoint(|bot: Bot, msg: Message, text: String| async move {
-                    let pattern = text.trim_start_matches("/check ").trim().to_string();
-                    if pattern.is_empty() {
+                    let args = text.trim_start_matches("/check ").split_whitespace().collect::<Vec<_>>();
+                    if args.is_empty() {
                         bot.send_message(msg.chat.id, "⚠️ Por favor, proporciona un patrón después de /check. Ejemplo: /check l[c]a-l[m]a").await?;
                         return Ok(());
                     }
-                    check_links(bot, msg, pattern).await
+                    match ScanRequest::parse(&args) {
+                        Ok(scan) => submit_scan(bot, &msg, scan).await,
+                        Err(e) => {
+                            bot.send_message(msg.chat.id, format!("⚠️ {}", e)).await?;
+                            Ok(())
+                        }
+                    }
                 })
         )
         .branch( // /yts_init command
@@ -473,6 +4295,184 @@ async fn main() {
                 .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
                 .filter(|text: String| text == "/yts_stop")
                 .endpoint(yts_stop_command)
+        )
+        .branch( // /yts_filter command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/yts_filter"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let args = text.trim_start_matches("/yts_filter").split_whitespace().map(ToOwned::to_owned).collect();
+                    yts_filter_command(bot, msg, args).await
+                })
+        )
+        .branch( // /yts_policy command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/yts_policy"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let args = text.trim_start_matches("/yts_policy").split_whitespace().map(ToOwned::to_owned).collect();
+                    yts_policy_command(bot, msg, args).await
+                })
+        )
+        .branch( // /yts_search command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/yts_search"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let args = text.trim_start_matches("/yts_search").split_whitespace().map(ToOwned::to_owned).collect();
+                    yts_search_command(bot, msg, args).await
+                })
+        )
+        .branch( // /yts_movie command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/yts_movie"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let key = text.trim_start_matches("/yts_movie").trim().to_string();
+                    if key.is_empty() {
+                        bot.send_message(msg.chat.id, "⚠️ Uso: /yts_movie <id|imdb>. Ejemplo: /yts_movie tt0078748").await?;
+                        return Ok(());
+                    }
+                    yts_movie_command(bot, msg, key).await
+                })
+        )
+        .branch( // /wants command (before /want, which is its prefix)
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text == "/wants")
+                .endpoint(wants_command)
+        )
+        .branch( // /want command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text == "/want" || text.starts_with("/want "))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let query = text.trim_start_matches("/want").trim().to_string();
+                    want_command(bot, msg, query).await
+                })
+        )
+        .branch( // /unwant command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/unwant"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let id = text.trim_start_matches("/unwant").trim().to_string();
+                    unwant_command(bot, msg, id).await
+                })
+        )
+        .branch( // /archive command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/archive"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let key = text.trim_start_matches("/archive").trim().to_string();
+                    if key.is_empty() {
+                        bot.send_message(
+                            msg.chat.id,
+                            format!("⚠️ Indica la URL o el id del hallazgo. Ejemplo: /archive {}", archive_id(&SiteProfile::builtin(), "lbA")),
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                    archive_command(bot, msg, key).await
+                })
+        )
+        .branch( // /recheck command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/recheck"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let key = text.trim_start_matches("/recheck").trim().to_string();
+                    if key.is_empty() {
+                        bot.send_message(
+                            msg.chat.id,
+                            format!("⚠️ Indica la URL o el id del hallazgo. Ejemplo: /recheck {}", archive_id(&SiteProfile::builtin(), "lbA")),
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                    recheck_command(bot, msg, key).await
+                })
+        )
+        .branch( // /pattern_save command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/pattern_save"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let args = text.trim_start_matches("/pattern_save").split_whitespace().map(ToOwned::to_owned).collect();
+                    pattern_save_command(bot, msg, args).await
+                })
+        )
+        .branch( // /pattern_list command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text == "/pattern_list")
+                .endpoint(pattern_list_command)
+        )
+        .branch( // /pattern_delete command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/pattern_delete"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let name = text.trim_start_matches("/pattern_delete").trim().to_string();
+                    if name.is_empty() {
+                        bot.send_message(msg.chat.id, "⚠️ Uso: /pattern_delete <nombre>").await?;
+                        return Ok(());
+                    }
+                    pattern_delete_command(bot, msg, name).await
+                })
+        )
+        .branch( // /run command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/run"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let name = text.trim_start_matches("/run").trim().to_string();
+                    if name.is_empty() {
+                        bot.send_message(msg.chat.id, "⚠️ Uso: /run <nombre>. Consulta /pattern_list.").await?;
+                        return Ok(());
+                    }
+                    run_command(bot, msg, name).await
+                })
+        )
+        .branch( // /jobs command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text == "/jobs")
+                .endpoint(jobs_command)
+        )
+        .branch( // /quota_set command (admins)
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/quota_set"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let args = text.trim_start_matches("/quota_set").split_whitespace().map(ToOwned::to_owned).collect();
+                    quota_set_command(bot, msg, args).await
+                })
+        )
+        .branch( // /quota command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text == "/quota")
+                .endpoint(quota_command)
+        )
+        .branch( // /schedule command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/schedule"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let args = text.trim_start_matches("/schedule").split_whitespace().map(ToOwned::to_owned).collect();
+                    schedule_command(bot, msg, args).await
+                })
+        )
+        .branch( // /timezone command
+            dptree::entry()
+                .filter_map(|msg: Message| msg.text().map(ToOwned::to_owned))
+                .filter(|text: String| text.starts_with("/timezone"))
+                .endpoint(|bot: Bot, msg: Message, text: String| async move {
+                    let tz_name = text.trim_start_matches("/timezone").trim().to_string();
+                    timezone_command(bot, msg, tz_name).await
+                })
         );
         // Add a default handler for unrecognised commands or text if desired
         // .branch(dptree::endpoint(|msg: Message, bot: Bot| async move {
@@ -480,6 +4480,10 @@ async fn main() {
         //     Ok(())
         // }));
 
+    // Inline keyboard buttons
+    let handler = dptree::entry()
+        .branch(message_handler)
+        .branch(Update::filter_callback_query().endpoint(callback_query_handler));
 
     Dispatcher::builder(bot, handler)
         .enable_ctrlc_handler()